@@ -1,14 +1,20 @@
 use core::panic;
 use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
 use near_sdk::{
   borsh::{self, BorshDeserialize, BorshSerialize},
   env, near_bindgen,
-  serde::{Deserialize, Serialize},
-  AccountId, Balance, Promise, ONE_NEAR,
+  serde::{Deserialize, Serialize, Serializer},
+  AccountId, Balance, Promise, PromiseResult, ONE_NEAR, ONE_YOCTO,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 
 // use crate::constants::*;
+use crate::bond::Bond;
+use crate::constants::{GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER};
+use crate::offerbook::NEAR_ASSET;
+use crate::reputation::Reputation;
 use crate::*;
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
@@ -43,6 +49,12 @@ pub struct PubAccountInfo {
   pub dislikes: i32,
   pub blocked_by: i32,
   pub created_on: Timestamp,
+  pub completed_trades: u32,
+  pub score: Option<f32>,             // Percent of rated volume rated positively
+  pub completion_rate: Option<f32>,   // Percent of opened chats that were released
+  pub cancellation_rate: Option<f32>, // Percent of opened chats that were canceled
+  pub avg_release_time: Option<u64>,  // Nanoseconds from chat start to release
+  pub verified_merchant: bool,
 }
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
@@ -55,8 +67,14 @@ pub struct PrivateAccountInfo {
   pub locked_tokens: HashMap<AccountId, Balance>,
 }
 
+// Token balances are kept per account under their own storage prefix, json
+// views list them like a map
+fn serialize_balances<S: Serializer>(balances: &UnorderedMap<AccountId, Balance>, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.collect_map(balances.iter())
+}
+
 // #[near_bindgen]
-#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[derive(Serialize, BorshSerialize, BorshDeserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Account {
   // Account identifier, matches the account ID
@@ -73,32 +91,73 @@ pub struct Account {
 
   // Dislikes of the account
   pub dislikes: i32,
+
   // Tokens
-  pub tokens: HashMap<AccountId, Balance>,
+  #[serde(serialize_with = "serialize_balances")]
+  pub tokens: UnorderedMap<AccountId, Balance>,
 
   // Locked tokens
-  pub locked_tokens: HashMap<AccountId, Balance>,
+  #[serde(serialize_with = "serialize_balances")]
+  pub locked_tokens: UnorderedMap<AccountId, Balance>,
 
   // Blocked accounts
-  // pub blocked_accounts: UnorderedSet<AccountId>,
+  pub blocked_accounts: HashSet<AccountId>,
   pub blocked_by: i32,
   pub created_on: Timestamp,
+
+  // Trade history and rating weights
+  pub reputation: Reputation,
+
+  // Merchant security bond
+  pub bond: Bond,
+}
+
+// Account loaded for changes, stored again once it goes out of scope
+pub struct AccountMut<'a> {
+  accounts: &'a mut UnorderedMap<AccountId, VersionedAccount>,
+  account: Option<Account>,
+}
+
+impl Deref for AccountMut<'_> {
+  type Target = Account;
+
+  fn deref(&self) -> &Account {
+    self.account.as_ref().unwrap()
+  }
+}
+
+impl DerefMut for AccountMut<'_> {
+  fn deref_mut(&mut self) -> &mut Account {
+    self.account.as_mut().unwrap()
+  }
+}
+
+impl Drop for AccountMut<'_> {
+  fn drop(&mut self) {
+    if let Some(account) = self.account.take() {
+      self.accounts.insert(&account.id.clone(), &VersionedAccount::from(account));
+    }
+  }
 }
 
 // imp for account and new, check whether the account is new or not
 // #[near_bindgen]
 impl Account {
   pub fn new(id: AccountId) -> Account {
+    let account_hash = env::sha256_array(id.as_bytes());
     Account {
       id,
       balance: 0,
       locked: 0,
       likes: 0,
       dislikes: 0,
-      tokens: HashMap::new(),
-      locked_tokens: HashMap::new(),
+      tokens: UnorderedMap::new(StorageKey::AccountTokens { account_hash }),
+      locked_tokens: UnorderedMap::new(StorageKey::AccountLockedTokens { account_hash }),
+      blocked_accounts: HashSet::new(),
       blocked_by: 0,
       created_on: env::block_timestamp(),
+      reputation: Reputation::default(),
+      bond: Bond::default(),
     }
   }
 
@@ -121,26 +180,24 @@ impl Account {
 
   pub fn deposit_tokens(&mut self, token_id: AccountId, amount: u128) {
     // assert!(ONE_NEAR <= amount, "Amount must be greater than 1 Near");
-    self.tokens.entry(token_id.clone()).or_insert(0);
-    self
-      .tokens
-      .insert(token_id.clone(), self.tokens[&token_id.clone()] + amount);
+    let balance = self.tokens.get(&token_id).unwrap_or(0);
+    self.tokens.insert(&token_id, &(balance + amount));
     // self.likes += 1;
     env::log_str("I have been reached");
   }
 
   pub fn withdraw_tokens(&mut self, token_id: AccountId, amount: u128) {
-    let token = self.tokens.get_mut(&token_id);
+    let token = self.tokens.get(&token_id);
     if token.is_none() {
       env::log_str("Token not found");
       return;
     }
     let token = token.unwrap();
-    if *token < amount {
+    if token < amount {
       env::log_str("Insufficient balance to make a withdrawal");
       return;
     }
-    *token -= amount;
+    self.tokens.insert(&token_id, &(token - amount));
   }
 
   pub fn lock(&mut self, amount: u128) {
@@ -163,41 +220,37 @@ impl Account {
 
   pub fn lock_tokens(&mut self, token_id: AccountId, amount: u128) {
     // Check whether the token balance is greater than the amount to be locked from the tokens
-    let token = self.tokens.get_mut(&token_id);
+    let token = self.tokens.get(&token_id);
     if token.is_none() {
       panic!("Token not found");
     }
     let token = token.unwrap();
-    if *token < amount.clone() {
+    if token < amount.clone() {
       panic!("Insufficient token balance to lock");
     }
     // Lock the tokens
-    self.locked_tokens.entry(token_id.clone()).or_insert(0);
-    self.locked_tokens.insert(
-      token_id.clone(),
-      self.locked_tokens[&token_id.clone()] + amount.clone(),
-    );
+    let locked = self.locked_tokens.get(&token_id).unwrap_or(0);
+    self.locked_tokens.insert(&token_id, &(locked + amount.clone()));
     // Subtract the locked tokens from the token balance in tokens
-    *token -= amount.clone();
+    self.tokens.insert(&token_id, &(token - amount.clone()));
   }
 
   pub fn unlock_tokens(&mut self, token_id: AccountId, amount: u128) {
-    let token = self.locked_tokens.get_mut(&token_id);
+    let token = self.locked_tokens.get(&token_id);
     if token.is_none() {
       panic!("Token not found");
     }
     let token = token.unwrap();
-    if *token < amount {
+    if token < amount {
       panic!("The token balance you are unlocking is more than the locked balance");
     }
-    *token -= amount;
+    self.locked_tokens.insert(&token_id, &(token - amount));
     // Add the token balance to the account tokens token balance
-    let mytoken = self.tokens.get_mut(&token_id.clone());
+    let mytoken = self.tokens.get(&token_id.clone());
     if mytoken.is_none() {
       panic!("Token not found");
     }
-    let mytoken = mytoken.unwrap();
-    *mytoken += amount;
+    self.tokens.insert(&token_id, &(mytoken.unwrap() + amount));
   }
 
   pub fn get_token_balance(&self, token_id: AccountId) -> Balance {
@@ -205,8 +258,7 @@ impl Account {
     if token.is_none() {
       panic!("Token not found");
     }
-    let token = token.unwrap();
-    *token
+    token.unwrap()
   }
 
   pub fn release(&mut self, amount: u128, to: AccountId) -> Promise {
@@ -224,7 +276,7 @@ impl Account {
       panic!("Token not found");
     }
     let locked_tokens = locked_tokens.unwrap();
-    if *locked_tokens < amount {
+    if locked_tokens < amount {
       panic!("Amount to release is greater than locked balance, can't proceed with transaction");
     }
     self.unlock_tokens(token_id.clone(), amount.clone());
@@ -247,6 +299,10 @@ impl Account {
     self.dislikes -= 1;
   }
 
+  pub fn has_blocked(&self, account_id: &AccountId) -> bool {
+    self.blocked_accounts.contains(account_id)
+  }
+
   pub fn get_likes(&self) -> i32 {
     self.likes
   }
@@ -264,61 +320,81 @@ impl Account {
   }
 }
 
+impl Contract {
+  pub(crate) fn get_account(&mut self, account_id: AccountId) -> Option<AccountMut<'_>> {
+    let account = self.accounts.get(&account_id).map(Account::from)?;
+    Some(AccountMut {
+      accounts: &mut self.accounts,
+      account: Some(account),
+    })
+  }
+}
+
 #[near_bindgen]
 impl Contract {
-  pub fn register_new_account(&mut self, account_id: AccountId) -> String {
+  pub fn register_new_account(&mut self, account_id: AccountId, referrer: Option<AccountId>) -> String {
+    self.assert_migrated();
     if self.get_account(account_id.clone()).is_none() {
+      // Only the account itself can name its referrer, registrations made for
+      // someone else (token deposits, third parties) never earn a referral
+      let referrer = referrer.filter(|_| env::predecessor_account_id() == account_id);
+      if let Some(referrer) = &referrer {
+        if let Some(err) = self.check_referrer(&account_id, referrer) {
+          return err;
+        }
+      }
       self
         .accounts
-        .insert(account_id.clone(), Account::new(account_id.clone()));
+        .insert(&account_id, &Account::new(account_id.clone()).into());
+      if let Some(referrer) = referrer {
+        self.add_referral(&account_id, referrer);
+      }
       return "Account registered successfully".to_string();
     } else {
       return "Account already registered".to_string();
     };
   }
 
-  pub fn get_account(&mut self, account_id: AccountId) -> Option<&mut Account> {
-    self.accounts.get_mut(&account_id)
-  }
-
-  pub fn pub_get_account(&self, account_id: AccountId) -> Option<&Account> {
-    self.accounts.get(&account_id)
+  pub fn pub_get_account(&self, account_id: AccountId) -> Option<Account> {
+    self.accounts.get(&account_id).map(Account::from)
   }
 
   #[payable]
   pub fn contract_deposit(&mut self, account_id: &AccountId) {
+    self.assert_not_paused(Subsystem::Deposits);
     assert!(
       env::attached_deposit() > ONE_NEAR,
       "Deposit must be greater than 1 Near"
     );
 
-    let acc = match self.get_account(account_id.clone()) {
-      Some(it) => it,
+    match self.get_account(account_id.clone()) {
+      Some(mut acc) => acc.deposit(env::attached_deposit()),
       _ => return,
     };
-    acc.deposit(env::attached_deposit());
     self.add_transfer(
-      env::block_timestamp().to_string(),
+      None,
       account_id.clone(),
       env::current_account_id(),
       env::attached_deposit(),
-    )
+    );
   }
 
   #[payable]
   pub fn withdraw_near(&mut self, amount: U128) -> Promise {
     let account_id = env::predecessor_account_id();
+    self.assert_not_denylisted(&account_id);
     let acc = self.get_account(account_id.clone());
     if acc.is_none() {
       panic!("Account not found");
     }
     assert!(
-      acc.as_ref().unwrap().clone().balance >= u128::from(amount.clone()),
+      acc.as_ref().unwrap().balance >= u128::from(amount.clone()),
       "Amount to withdraw is greater than balance, can't proceed with transaction"
     );
     acc.unwrap().withdraw(u128::from(amount.clone()));
+    self.use_withdrawal_allowance(&account_id, offerbook::NEAR_ASSET, amount.0);
     self.add_transfer(
-      env::block_timestamp().to_string(),
+      None,
       env::current_account_id(),
       account_id.clone(),
       u128::from(amount),
@@ -326,12 +402,12 @@ impl Contract {
     Promise::new(account_id.clone()).transfer(u128::from(amount.clone()))
   }
 
-  pub fn get_all_accounts(&mut self) -> Vec<&Account> {
+  pub fn get_all_accounts(&self) -> Vec<Account> {
     assert!(
       env::predecessor_account_id() == env::current_account_id(),
       "Only contract owner can call this method"
     );
-    let input = self.accounts.values();
+    let input = self.accounts.values().map(Account::from);
     let mut output = Vec::new();
 
     for s in input {
@@ -342,7 +418,7 @@ impl Contract {
   }
 
   pub fn get_account_balance_as_string(&mut self, account_id: &AccountId) -> String {
-    let acc = match self.get_account(account_id.clone()) {
+    let acc = match self.pub_get_account(account_id.clone()) {
       Some(it) => it,
       _ => panic!("Account not found"),
     };
@@ -359,7 +435,8 @@ impl Contract {
   }
 
   pub fn unlock_account(&mut self, account_id: AccountId, amount: u128) {
-    let acc = match self.get_account(account_id.clone()) {
+    self.assert_role(Role::Arbiter);
+    let mut acc = match self.get_account(account_id.clone()) {
       Some(it) => it,
       _ => panic!("Account not found"),
     };
@@ -384,48 +461,200 @@ impl Contract {
     return len.try_into().unwrap();
   }
 
+  pub fn block_account(&mut self, account_id: AccountId) {
+    let me = env::predecessor_account_id();
+    assert_ne!(me, account_id, "You can't block yourself");
+    assert!(self.accounts.get(&account_id).is_some(), "Account not found");
+    let newly_blocked = self.get_account(me).expect("Account not found").blocked_accounts.insert(account_id.clone());
+    if newly_blocked {
+      self.get_account(account_id).unwrap().blocked_by += 1;
+    }
+  }
+
+  pub fn unblock_account(&mut self, account_id: AccountId) {
+    let me = env::predecessor_account_id();
+    let unblocked = self.get_account(me).expect("Account not found").blocked_accounts.remove(&account_id);
+    if unblocked {
+      if let Some(mut blocked) = self.get_account(account_id) {
+        blocked.blocked_by -= 1;
+      }
+    }
+  }
+
+  pub fn get_blocked_accounts(&self, account_id: AccountId) -> Vec<AccountId> {
+    self
+      .pub_get_account(account_id)
+      .map(|acc| acc.blocked_accounts.iter().cloned().collect())
+      .unwrap_or_default()
+  }
+
+  // True when either account has blocked the other
+  pub fn is_blocked_between(&self, a: AccountId, b: AccountId) -> bool {
+    let blocked = |from: &AccountId, to: &AccountId| {
+      self.pub_get_account(from.clone()).is_some_and(|acc| acc.has_blocked(to))
+    };
+    blocked(&a, &b) || blocked(&b, &a)
+  }
+
   pub fn acc_pub_info(&self, account_id: AccountId) -> Option<PubAccountInfo> {
-    let acc = self.pub_get_account(account_id.clone());
-    if acc.as_ref().is_some() {
-      let acc_info = PubAccountInfo {
-        id: acc.unwrap().id.clone(),
-        likes: acc.unwrap().likes,
-        trades: self.get_trades_length_by_account(account_id.clone()),
-        transfers: self.get_transfers_len_by_account(account_id.clone()),
-        offers: self.get_offers_len_by_account(account_id.clone())
-          + self.get_t_offers_len_by_account(account_id.clone()),
-        dislikes: acc.unwrap().dislikes,
-        blocked_by: acc.unwrap().blocked_by,
-        created_on: acc.unwrap().created_on,
-      };
-      return Some(acc_info);
-    }
-    return None;
+    let acc = self.pub_get_account(account_id.clone())?;
+    Some(PubAccountInfo {
+      id: acc.id.clone(),
+      likes: acc.likes,
+      trades: self.get_trades_length_by_account(account_id.clone()),
+      transfers: self.get_transfers_len_by_account(account_id.clone()),
+      offers: self.get_offers_len_by_account(account_id.clone())
+        + self.get_t_offers_len_by_account(account_id.clone()),
+      dislikes: acc.dislikes,
+      blocked_by: acc.blocked_by,
+      created_on: acc.created_on,
+      completed_trades: acc.reputation.completed,
+      score: acc.reputation.score(),
+      completion_rate: acc.reputation.completion_rate(),
+      cancellation_rate: acc.reputation.cancellation_rate(),
+      avg_release_time: acc.reputation.average_release_time(),
+      verified_merchant: self.is_verified_merchant(account_id),
+    })
   }
 
   pub fn acc_private_info(&self, account_id: AccountId) -> Option<PrivateAccountInfo> {
-    let acc = self.pub_get_account(account_id.clone());
-    if acc.as_ref().is_some() {
-      let acc_info = PubAccountInfo {
-        id: acc.unwrap().id.clone(),
-        likes: acc.unwrap().likes,
-        trades: self.get_trades_length_by_account(account_id.clone()),
-        transfers: self.get_transfers_len_by_account(account_id.clone()),
-        offers: self.get_offers_len_by_account(account_id.clone())
-          + self.get_t_offers_len_by_account(account_id.clone()),
-        dislikes: acc.unwrap().dislikes,
-        blocked_by: acc.unwrap().blocked_by,
-        created_on: acc.unwrap().created_on,
-      };
-      let priv_acc_info = PrivateAccountInfo {
-        info: acc_info,
-        balance: acc.unwrap().balance,
-        locked: acc.unwrap().locked,
-        tokens: acc.unwrap().tokens.clone(),
-        locked_tokens: acc.unwrap().locked_tokens.clone(),
-      };
-      return Some(priv_acc_info);
-    }
-    return None;
+    let acc = self.pub_get_account(account_id.clone())?;
+    Some(PrivateAccountInfo {
+      info: self.acc_pub_info(account_id)?,
+      balance: acc.balance,
+      locked: acc.locked,
+      tokens: acc.tokens.iter().collect(),
+      locked_tokens: acc.locked_tokens.iter().collect(),
+    })
+  }
+
+  // Returns why the account can't be closed yet, if it can't
+  fn check_account_can_close(&self, account_id: &AccountId) -> Option<String> {
+    let acc = match self.pub_get_account(account_id.clone()) {
+      Some(acc) => acc,
+      None => return Some("Account not found".to_string()),
+    };
+    if let Some(err) = self.check_denylisted(&[account_id]) {
+      return Some(err);
+    }
+    let in_chat = |payer: &AccountId, receiver: &AccountId| payer == account_id || receiver == account_id;
+    if self.chats.values().map(Chat::from).any(|chat| chat.active && in_chat(&chat.payer, &chat.receiver))
+      || self.tokenchats.values().map(TokenChat::from).any(|chat| chat.active && in_chat(&chat.payer, &chat.receiver))
+    {
+      return Some("Finish or cancel your open chats before closing the account".to_string());
+    }
+    if self.get_account_offers(account_id.clone()).iter().any(|offer| offer.active)
+      || self.get_account_token_offers(account_id.clone()).iter().any(|offer| offer.active)
+    {
+      return Some("Deactivate your offers before closing the account".to_string());
+    }
+    if acc.locked > 0 || acc.locked_tokens.values().any(|locked| locked > 0) {
+      return Some("You have funds locked in chats".to_string());
+    }
+    if acc.bond.total() > 0 {
+      return Some("Withdraw your bond before closing the account".to_string());
+    }
+    let above_limit = |asset: &str, amount: Balance| {
+      amount > self.get_allowance(account_id.clone(), Some(asset.to_string())).withdrawal_remaining.0
+    };
+    if above_limit(NEAR_ASSET, acc.balance) || acc.tokens.iter().any(|(token, amount)| above_limit(token.as_str(), amount)) {
+      return Some("Your balance is above your daily withdrawal limit, withdraw part of it first".to_string());
+    }
+    None
+  }
+
+  /// Withdraws every Near and token balance to the caller and removes the
+  /// account with its offers. Token transfers that fail are credited back by
+  /// on_close_account_token, which restores the closed account record.
+  pub fn close_account(&mut self) -> String {
+    let account_id = env::predecessor_account_id();
+    if let Some(err) = self.check_account_can_close(&account_id) {
+      return err;
+    }
+    let mut acc = Account::from(self.accounts.remove(&account_id).unwrap());
+    let tokens: Vec<(AccountId, Balance)> = acc.tokens.iter().filter(|(_, amount)| *amount > 0).collect();
+    if !tokens.is_empty() {
+      self.assert_not_paused(Subsystem::TokenWithdrawals);
+    }
+    let balance = acc.balance;
+    self.use_withdrawal_allowance(&account_id, NEAR_ASSET, balance);
+    for (token, amount) in tokens.iter() {
+      self.use_withdrawal_allowance(&account_id, token.as_str(), *amount);
+    }
+
+    for blocked in std::mem::take(&mut acc.blocked_accounts) {
+      if let Some(mut blocked) = self.get_account(blocked) {
+        blocked.blocked_by -= 1;
+      }
+    }
+    // What is left of the record once the balances are sent, restored if a token transfer fails
+    acc.balance = 0;
+    acc.tokens.clear();
+    acc.locked_tokens.clear();
+    let closed = acc.try_to_vec().unwrap();
+
+    for offer in self.get_account_offers(account_id.clone()) {
+      self.offers.remove(&offer.id);
+      self.unindex_offer(NEAR_ASSET, &offer.currency, &offer.id);
+      if let Some(mut chat_ids) = self.offer_chats.remove(&offer.id) {
+        chat_ids.clear();
+      }
+    }
+    for offer in self.get_account_token_offers(account_id.clone()) {
+      self.tokenoffers.remove(&offer.id);
+      self.unindex_offer(offer.token.as_str(), &offer.currency, &offer.id);
+      if let Some(mut chat_ids) = self.token_offer_chats.remove(&offer.id) {
+        chat_ids.clear();
+      }
+    }
+
+    if balance > 0 {
+      self.add_transfer(None, env::current_account_id(), account_id.clone(), balance);
+      Promise::new(account_id.clone()).transfer(balance);
+    }
+    for (token, amount) in tokens {
+      Promise::new(token.clone())
+        .function_call(
+          "ft_transfer".to_string(),
+          json!({ "receiver_id": account_id.clone(), "amount": U128(amount) })
+            .to_string()
+            .into_bytes(),
+          ONE_YOCTO,
+          GAS_FOR_FT_TRANSFER,
+        )
+        .then(Promise::new(env::current_account_id()).function_call(
+          "on_close_account_token".to_string(),
+          // Borsh, the record has u128 fields json can't carry
+          [closed.clone(), (token, U128(amount)).try_to_vec().unwrap()].concat(),
+          0,
+          GAS_FOR_BASIC_OP,
+        ));
+    }
+    "Account closed".to_string()
+  }
+
+  #[private]
+  pub fn on_close_account_token(
+    &mut self,
+    #[serializer(borsh)] account: Account,
+    #[serializer(borsh)] token_id: AccountId,
+    #[serializer(borsh)] amount: U128,
+  ) {
+    let account_id = account.id.clone();
+    match env::promise_result(0) {
+      PromiseResult::NotReady => unreachable!(),
+      PromiseResult::Successful(_) => {
+        self.add_transfer(None, env::current_account_id(), account_id, amount.0);
+      }
+      PromiseResult::Failed => {
+        env::log_str(&format!("Withdrawal of {} failed, crediting it back", token_id));
+        // Keeps the history and reputation of the closed account, unless the
+        // owner registered again since
+        if self.accounts.get(&account_id).is_none() {
+          self.accounts.insert(&account_id, &VersionedAccount::from(account));
+        }
+        self.get_account(account_id).unwrap().deposit_tokens(token_id, amount.0);
+      }
+    }
   }
 }
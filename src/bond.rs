@@ -0,0 +1,169 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Promise, Timestamp, ONE_NEAR,
+};
+
+use crate::*;
+
+// Near staked by a merchant, slashable until it is withdrawn
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bond {
+  pub staked: Balance,
+  pub unstaking: Balance, // Requested for withdrawal, waiting for the cooldown
+  pub unlock_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BondConfig {
+  pub verified_bond: U128,     // Staked bond needed for the verified merchant flag
+  pub base_offer_limit: U128,  // Max offer amount without a bond
+  pub bond_multiplier: u32,    // Each staked Near raises the offer limit by this many Near
+  pub cooldown_sec: u64,       // Wait between a withdrawal request and the withdrawal
+}
+
+impl Default for BondConfig {
+  fn default() -> Self {
+    Self {
+      verified_bond: U128(50 * ONE_NEAR),
+      base_offer_limit: U128(100 * ONE_NEAR),
+      bond_multiplier: 10,
+      cooldown_sec: 7 * 24 * 60 * 60,
+    }
+  }
+}
+
+impl Bond {
+  pub fn total(&self) -> Balance {
+    self.staked + self.unstaking
+  }
+
+  // Takes the amount from the unstaking part first, then from the staked bond
+  pub fn slash(&mut self, amount: Balance) {
+    assert!(self.total() >= amount, "Amount to slash is greater than the bond");
+    let from_unstaking = amount.min(self.unstaking);
+    self.unstaking -= from_unstaking;
+    self.staked -= amount - from_unstaking;
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn set_bond_config(&mut self, config: BondConfig) {
+    self.assert_role(Role::Admin);
+    self.bond_config = config;
+  }
+
+  pub fn get_bond_config(&self) -> BondConfig {
+    self.bond_config.clone()
+  }
+
+  pub fn get_bond(&self, account_id: AccountId) -> Option<Bond> {
+    self.pub_get_account(account_id).map(|acc| acc.bond.clone())
+  }
+
+  pub fn is_verified_merchant(&self, account_id: AccountId) -> bool {
+    self
+      .pub_get_account(account_id)
+      .is_some_and(|acc| acc.bond.staked >= self.bond_config.verified_bond.0)
+  }
+
+  // Largest max_amount the account can list on a Near offer
+  pub fn get_offer_limit(&self, account_id: AccountId) -> U128 {
+    let staked = self.pub_get_account(account_id).map_or(0, |acc| acc.bond.staked);
+    U128(self.bond_config.base_offer_limit.0 + staked * u128::from(self.bond_config.bond_multiplier))
+  }
+
+  #[payable]
+  pub fn stake_bond(&mut self) -> U128 {
+    self.assert_not_paused(Subsystem::Deposits);
+    let amount = env::attached_deposit();
+    assert!(amount > 0, "Attach the Near to stake as bond");
+    let mut acc = self
+      .get_account(env::predecessor_account_id())
+      .expect("Account not found");
+    acc.bond.staked += amount;
+    U128(acc.bond.staked)
+  }
+
+  /// Starts the cooldown for part of the bond. Requesting again adds to the
+  /// pending amount and restarts the cooldown.
+  pub fn request_bond_withdrawal(&mut self, amount: U128) -> Timestamp {
+    let unlock_at = env::block_timestamp() + self.bond_config.cooldown_sec * 1_000_000_000;
+    let mut acc = self
+      .get_account(env::predecessor_account_id())
+      .expect("Account not found");
+    assert!(acc.bond.staked >= amount.0, "Amount is greater than the staked bond");
+    acc.bond.staked -= amount.0;
+    acc.bond.unstaking += amount.0;
+    acc.bond.unlock_at = Some(unlock_at);
+    unlock_at
+  }
+
+  pub fn withdraw_bond(&mut self) -> Promise {
+    let account_id = env::predecessor_account_id();
+    self.assert_not_denylisted(&account_id);
+    let mut acc = self.get_account(account_id.clone()).expect("Account not found");
+    assert!(acc.bond.unstaking > 0, "No bond withdrawal requested");
+    assert!(
+      acc.bond.unlock_at.unwrap_or(0) <= env::block_timestamp(),
+      "Bond is still in its cooldown"
+    );
+    let amount = acc.bond.unstaking;
+    acc.bond.unstaking = 0;
+    acc.bond.unlock_at = None;
+    drop(acc);
+    self.use_withdrawal_allowance(&account_id, offerbook::NEAR_ASSET, amount);
+    self.add_transfer(None, env::current_account_id(), account_id.clone(), amount);
+    Promise::new(account_id).transfer(amount)
+  }
+
+  /// Slashes the bond of the participant a dispute on `chat_id` was ruled
+  /// against. The amount goes to the counterparty's balance, or to the
+  /// contract revenue when no beneficiary is given.
+  pub fn slash_bond(&mut self, chat_id: String, account_id: AccountId, amount: U128, beneficiary: Option<AccountId>) {
+    self.assert_role_or_guardian(Role::Arbiter);
+    self.assert_not_emergency();
+    let (payer, receiver) = match self.chats.get(&chat_id).map(Chat::from) {
+      Some(chat) => (chat.payer, chat.receiver),
+      None => {
+        let chat = self.tokenchats.get(&chat_id).map(TokenChat::from).expect("Chat not found");
+        (chat.payer, chat.receiver)
+      }
+    };
+    let counterparty = if account_id == payer {
+      receiver
+    } else if account_id == receiver {
+      payer
+    } else {
+      panic!("Account is not a participant of the chat")
+    };
+
+    self
+      .get_account(account_id.clone())
+      .expect("Account not found")
+      .bond
+      .slash(amount.0);
+
+    match beneficiary {
+      Some(beneficiary) => {
+        assert_eq!(beneficiary, counterparty, "Slashed bond can only go to the counterparty");
+        self.get_account(beneficiary.clone()).expect("Account not found").balance += amount.0;
+        self.add_transfer(None, account_id, beneficiary, amount.0);
+      }
+      None => {
+        self.revenue += amount.0;
+        self.revenues.insert(&Revenue {
+          from: "slash".to_string(),
+          asset: "near".to_string(),
+          amount_usd: 0.0,
+          amount: amount.0,
+          date: env::block_timestamp(),
+        });
+      }
+    }
+  }
+}
@@ -4,12 +4,27 @@ use near_sdk::json_types::U128;
 use near_sdk::{
   borsh::{BorshDeserialize, BorshSerialize},
   serde::{Deserialize, Serialize},
-  AccountId, Balance, Promise, Timestamp,
+  AccountId, Balance, Promise, Timestamp, ONE_NEAR,
 };
 
 use crate::offer::CompleteOffer;
+use crate::ids::CHAT_ID_PREFIX;
 use crate::*;
 
+pub fn fiat_total(amount: Balance, rate: f32) -> f64 {
+  amount as f64 / ONE_NEAR as f64 * rate as f64
+}
+
+// Returns who escrows the chat amount and who gets it on release. Buy chats
+// escrow the payer's funds, sell chats the receiver's.
+pub fn escrow_sides(offer_type: &str, payer: &AccountId, receiver: &AccountId) -> (AccountId, AccountId) {
+  if offer_type == "buy" {
+    (payer.clone(), receiver.clone())
+  } else {
+    (receiver.clone(), payer.clone())
+  }
+}
+
 // #[near_bindgen]
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
@@ -19,6 +34,8 @@ pub struct Chat {
   pub owner: AccountId,
   pub offerer: AccountId,
   pub amount: Balance,
+  pub rate: f32, // Offer rate frozen when the chat was opened, or the negotiated one
+  pub fiat_total: f64, // What the payer owes in the offer currency at that rate
   pub trade_cost: Balance,
   pub trade_cost_usd: f64,
   pub started_at: Timestamp,
@@ -30,8 +47,11 @@ pub struct Chat {
   pub received: bool,
   pub canceled: bool,
   pub released: bool,
+  pub released_amount: Balance, // Escrow released so far, partial releases add up to amount
+  pub fee_collected: Balance,   // Part of trade_cost taken with the partial releases
   pub payer_has_rated: bool,
   pub receiver_has_rated: bool,
+  pub payment_method: String, // Payment method picked from the offer
   pub payment_msg: String,
   pub created_on: Timestamp,
   pub updated_on: Option<Timestamp>,
@@ -52,10 +72,12 @@ impl Chat {
     owner: AccountId,
     offerer: AccountId,
     amount: Balance,
+    rate: f32,
     trade_cost: Balance,
     trade_cost_usd: f64,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
   ) -> Self {
     Self {
@@ -64,6 +86,8 @@ impl Chat {
       owner,
       offerer,
       amount,
+      rate,
+      fiat_total: fiat_total(amount, rate),
       trade_cost,
       trade_cost_usd,
       started_at: env::block_timestamp(),
@@ -75,14 +99,22 @@ impl Chat {
       received: false,
       canceled: false,
       released: false,
+      released_amount: 0,
+      fee_collected: 0,
       payer_has_rated: false,
       receiver_has_rated: false,
+      payment_method,
       payment_msg,
       created_on: env::block_timestamp(),
       updated_on: Some(env::block_timestamp()),
     }
   }
 
+  pub fn set_agreed_rate(&mut self, rate: f32) {
+    self.rate = rate;
+    self.fiat_total = fiat_total(self.amount, rate);
+  }
+
   pub fn update_ended_at(&mut self) {
     self.ended_at = Some(env::block_timestamp());
   }
@@ -111,6 +143,8 @@ impl Chat {
 
   pub fn mark_as_released(&mut self) {
     self.released = true;
+    self.released_amount = self.amount;
+    self.fee_collected = self.trade_cost;
   }
 
   pub fn mark_as_canceled(&mut self) -> String {
@@ -124,152 +158,286 @@ impl Chat {
   }
 }
 
-#[near_bindgen]
 impl Contract {
-  pub fn add_buy_chat(
+  // Only the side whose funds are escrowed can release them
+  pub(crate) fn assert_escrow_holder(&self, locker: &AccountId) {
+    assert!(
+      env::predecessor_account_id() == *locker,
+      "Only the account holding the escrow can release it"
+    );
+  }
+
+  // Cancellations count against both sides' reputation, so only they or an
+  // arbiter can cancel
+  pub(crate) fn assert_can_cancel(&self, payer: &AccountId, receiver: &AccountId) {
+    let caller = env::predecessor_account_id();
+    assert!(
+      caller == *payer || caller == *receiver || self.has_role_or_admin(Role::Arbiter, &caller),
+      "Only the chat participants or an arbiter can cancel it"
+    );
+  }
+
+  pub(crate) fn index_offer_chat(&mut self, offer_id: &String, chat_id: &String) {
+    let mut chat_ids = self.offer_chats.get(offer_id).unwrap_or_else(|| {
+      UnorderedSet::new(StorageKey::OfferChatIds {
+        offer_hash: env::sha256_array(offer_id.as_bytes()),
+      })
+    });
+    chat_ids.insert(chat_id);
+    self.offer_chats.insert(offer_id, &chat_ids);
+  }
+
+  // Opens a chat on a buy offer and locks the amount and fee of the payer,
+  // the error is why it couldn't be opened
+  pub(crate) fn open_buy_chat(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_id: String,
     owner: AccountId,
     amount: U128,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
-    trade_cost: U128,
-    trade_cost_usd: f64
-  ) -> String {
-
+  ) -> Result<String, String> {
+    self.assert_not_paused(Subsystem::Chats);
+    // The fee is send_cost after the volume discount of the account locking it
+    let (fee, trade_cost_usd) = self.trade_fee(&payer, offerbook::NEAR_ASSET);
+    let trade_cost = U128(fee);
     let offer = self.get_offer(offer_id.clone());
 
     if owner.clone() == offer.as_ref().unwrap().offerer.clone() {
-      return "You can't chat with yourself".to_string();
+      return Err("You can't chat with yourself".to_string());
+    }
+    if self.is_blocked_between(owner.clone(), offer.as_ref().unwrap().offerer.clone()) {
+      return Err("You can't open a chat with this account".to_string());
+    }
+    if let Some(err) = self.check_offer_requirements(&offer.as_ref().unwrap().requirements, owner.clone()) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_denylisted(&[&payer, &receiver]) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_trade_limits(offerbook::NEAR_ASSET, amount.0, [&payer, &receiver]) {
+      return Err(err);
     }
     if offer.as_ref().is_none() {
-      return "Offer not found".to_string();
+      return Err("Offer not found".to_string());
     } else {
       if offer.as_ref().unwrap().offer_type.clone() == "buy".to_string() {
-        let chat_initiator = self.get_account(payer.clone());
+        match offer.as_ref().unwrap().get_payment(&payment_method) {
+          None => return Err("Payment method is not accepted by this offer".to_string()),
+          Some(payment) if !payment.accepts(u128::from(amount)) => {
+            return Err("Amount is outside the limits of the payment method".to_string())
+          }
+          _ => {}
+        }
+        let rate = match self.resolve_offer_rate(offer.as_ref().unwrap()) {
+          Some(rate) => rate,
+          None => return Err("Oracle price is stale, refresh it before opening the chat".to_string()),
+        };
+        let chat_initiator = self.pub_get_account(payer.clone());
         if chat_initiator.as_ref().is_none() {
-          return "You must be a registered user to chat with someone".to_string();
+          return Err("You must be a registered user to chat with someone".to_string());
         } else {
-          if chat_initiator.as_ref().unwrap().clone().balance
+          if chat_initiator.as_ref().unwrap().balance
             < (u128::from(amount.clone()) + u128::from(trade_cost.clone()))
           {
-            return "You don't have enough balance to chat with someone".to_string();
+            return Err("You don't have enough balance to chat with someone".to_string());
           } else {
+            let id = self.resolve_id(CHAT_ID_PREFIX, id, |c, id| c.chats.get(id).is_some())?;
             self.chats.insert(
               &id.clone(),
-              &Chat::new(
+              &VersionedChat::from(Chat::new(
                 id.clone(),
                 offer_id.clone(),
                 owner.clone(),
                 offer.as_ref().unwrap().offerer.clone(),
                 u128::from(amount.clone()),
+                rate,
                 u128::from(trade_cost.clone()),
                 trade_cost_usd,
                 payer.clone(),
                 receiver.clone(),
+                payment_method,
                 payment_msg,
-              ),
+              )),
             );
             self
               .get_account(payer.clone())
               .unwrap()
               .lock(u128::from(amount.clone()) + u128::from(trade_cost.clone()));
-            return "created".to_string();
+            self.record_chat_opened(&payer, &receiver);
+            self.index_offer_chat(&offer_id, &id);
+            self.record_trade_usage(offerbook::NEAR_ASSET, amount.0, [&payer, &receiver]);
+            return Ok(id);
           }
         }
       }
-      return "Offer is not for buy".to_string();
+      return Err("Offer is not for buy".to_string());
     }
   }
 
-  pub fn add_sell_chat(
+  // Opens a chat on a sell offer and locks the amount and fee of the offerer,
+  // the error is why it couldn't be opened
+  pub(crate) fn open_sell_chat(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_id: String,
     owner: AccountId,
     amount: U128,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
-    trade_cost: U128,
-    trade_cost_usd: f64
-  ) -> String {
-
+  ) -> Result<String, String> {
+    self.assert_not_paused(Subsystem::Chats);
+    // The fee is send_cost after the volume discount of the account locking it
+    let (fee, trade_cost_usd) = self.trade_fee(&receiver, offerbook::NEAR_ASSET);
+    let trade_cost = U128(fee);
     let offer = self.get_offer(offer_id.clone());
 
     if owner.clone() == offer.as_ref().unwrap().offerer.clone() {
-      return "You can't chat with yourself".to_string();
+      return Err("You can't chat with yourself".to_string());
+    }
+    if self.is_blocked_between(owner.clone(), offer.as_ref().unwrap().offerer.clone()) {
+      return Err("You can't open a chat with this account".to_string());
+    }
+    if let Some(err) = self.check_offer_requirements(&offer.as_ref().unwrap().requirements, owner.clone()) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_denylisted(&[&payer, &receiver]) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_trade_limits(offerbook::NEAR_ASSET, amount.0, [&payer, &receiver]) {
+      return Err(err);
     }
     if offer.as_ref().is_none() {
-      return "Offer not found".to_string();
+      return Err("Offer not found".to_string());
     } else {
       if offer.as_ref().unwrap().offer_type.clone() == "sell".to_string() {
-        let offer_owner = self.get_account(receiver.clone());
+        match offer.as_ref().unwrap().get_payment(&payment_method) {
+          None => return Err("Payment method is not accepted by this offer".to_string()),
+          Some(payment) if !payment.accepts(u128::from(amount)) => {
+            return Err("Amount is outside the limits of the payment method".to_string())
+          }
+          _ => {}
+        }
+        let rate = match self.resolve_offer_rate(offer.as_ref().unwrap()) {
+          Some(rate) => rate,
+          None => return Err("Oracle price is stale, refresh it before opening the chat".to_string()),
+        };
+        let offer_owner = self.pub_get_account(receiver.clone());
         if offer_owner.as_ref().unwrap().balance < (u128::from(amount.clone()) + u128::from(trade_cost.clone()))
         {
-          return "Offerer does not have sufficient balance to hold the trade. ".to_string();
+          return Err("Offerer does not have sufficient balance to hold the trade. ".to_string());
         } else {
+          let id = self.resolve_id(CHAT_ID_PREFIX, id, |c, id| c.chats.get(id).is_some())?;
           self.chats.insert(
             &id.clone(),
-            &Chat::new(
-              id,
-              offer_id,
+            &VersionedChat::from(Chat::new(
+              id.clone(),
+              offer_id.clone(),
               owner,
               offer.as_ref().unwrap().offerer.clone(),
               u128::from(amount.clone()),
+              rate,
               u128::from(trade_cost.clone()),
                 trade_cost_usd,
               payer.clone(),
               receiver.clone(),
+              payment_method,
               payment_msg,
-            ),
+            )),
           );
           self
             .get_account(receiver.clone())
             .unwrap()
             .lock(u128::from(amount.clone()) + u128::from(trade_cost.clone()));
-          return "created".to_string();
+          self.record_chat_opened(&payer, &receiver);
+          self.index_offer_chat(&offer_id, &id);
+          self.record_trade_usage(offerbook::NEAR_ASSET, amount.0, [&payer, &receiver]);
+          return Ok(id);
         }
       }
-      return "Offer is not for sell".to_string();
+      return Err("Offer is not for sell".to_string());
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn add_buy_chat(
+    &mut self,
+    id: Option<String>,
+    offer_id: String,
+    owner: AccountId,
+    amount: U128,
+    payer: AccountId,
+    receiver: AccountId,
+    payment_method: String,
+    payment_msg: String,
+  ) -> String {
+    match self.open_buy_chat(id, offer_id, owner, amount, payer, receiver, payment_method, payment_msg) {
+      Ok(id) => id,
+      Err(err) => err,
+    }
+  }
+
+  pub fn add_sell_chat(
+    &mut self,
+    id: Option<String>,
+    offer_id: String,
+    owner: AccountId,
+    amount: U128,
+    payer: AccountId,
+    receiver: AccountId,
+    payment_method: String,
+    payment_msg: String,
+  ) -> String {
+    match self.open_sell_chat(id, offer_id, owner, amount, payer, receiver, payment_method, payment_msg) {
+      Ok(id) => id,
+      Err(err) => err,
     }
   }
 
   pub fn get_chat(&mut self, chat_id: String) -> Option<Chat> {
-    self.chats.get(&chat_id)
+    self.chats.get(&chat_id).map(Chat::from)
   }
 
   pub fn pub_get_chat(&self, chat_id: String) -> CompleteChat {
-    let chat = self.chats.get(&chat_id);
+    let chat = self.chats.get(&chat_id).map(Chat::from);
     let offer = self.pub_get_offer(chat.as_ref().unwrap().offer_id.clone());
     CompleteChat { chat, offer }
   }
 
   pub fn mark_as_paid(&mut self, chat_id: String) -> String {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.chats.remove(&chat_id.clone()).map(Chat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
 
     if chat.clone().active {
       chat.mark_as_paid();
-      self.chats.insert(&chat_id.clone(), &chat.clone());
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
       return "success".to_string();
     } else {
-      self.chats.insert(&chat_id.clone(), &chat.clone());
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
 
   pub fn mark_as_received(&mut self, chat_id: String) -> String {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.chats.remove(&chat_id.clone()).map(Chat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
 
     if chat.clone().active {
       chat.mark_as_received();
-      self.chats.insert(&chat_id.clone(), &chat.clone());
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
       self.release_near(chat_id.clone());
       return "success".to_string();
     } else {
-      self.chats.insert(&chat_id.clone(), &chat.clone());
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
@@ -280,7 +448,7 @@ impl Contract {
 
   pub fn get_account_chats(&self, account_id: AccountId) -> Vec<Chat> {
     let mut chats = Vec::new();
-    self.chats.to_vec().into_iter().for_each(|(_id, chat)| {
+    self.chats.values().map(Chat::from).for_each(|chat| {
       if chat.owner == account_id || chat.offerer == account_id {
         chats.push(chat)
       }
@@ -289,11 +457,14 @@ impl Contract {
   }
 
   pub fn clear_chats(&mut self) {
+    self.assert_role(Role::Admin);
     self.chats.clear();
   }
 
   pub fn cancel_chat(&mut self, chat_id: String) -> String {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
+    let mut chat = self.chats.remove(&chat_id.clone()).map(Chat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
+    self.assert_can_cancel(&chat.payer, &chat.receiver);
     let offer = self.get_offer(chat.clone().offer_id).unwrap();
 
     if chat.clone().active {
@@ -301,27 +472,37 @@ impl Contract {
         self
           .get_account(chat.clone().owner)
           .unwrap()
-          .unlock(u128::from(chat.clone().amount));
+          .unlock(chat.amount - chat.released_amount);
       } else {
         self
           .get_account(offer.offerer.clone())
           .unwrap()
-          .unlock(u128::from(chat.clone().amount));
+          .unlock(chat.amount - chat.released_amount);
       }
       chat.mark_as_canceled();
-      self.chats.insert(&chat_id.clone(), &chat);
+      if chat.canceled {
+        self.record_chat_canceled(&chat.payer, &chat.receiver);
+      }
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat));
       env::log_str("We have canceled the chat");
       return "chat canceled".to_string();
     } else {
       env::log_str("Chat is not active, can't be canceled");
-      self.chats.insert(&chat_id.clone(), &chat.clone());
+      self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
 
   pub fn release_near(&mut self, chat_id: String) -> Promise {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.chats.remove(&chat_id.clone()).map(Chat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
     let offer = self.get_offer(chat.offer_id.clone()).unwrap();
+    self.assert_escrow_holder(&escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver).0);
+    // Partial releases already moved part of the escrow and fee
+    let remaining = chat.amount - chat.released_amount;
+    let fee = chat.trade_cost - chat.fee_collected;
+    let fee_usd = chat.trade_cost_usd * fee as f64 / chat.trade_cost.max(1) as f64;
 
     if offer.offer_type.clone() == "buy".to_string() {
       // let trade = Trade::new(
@@ -336,23 +517,25 @@ impl Contract {
       //   Some(env::block_timestamp()),
       // );
       if chat.clone().released {
-        self.chats.insert(&chat_id.clone(), &chat.clone());
+        self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
         panic!("Amount already released");
       } else {
         chat.mark_as_released();
-        self.chats.insert(&chat_id.clone(), &chat.clone());
+        self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
+        self.record_chat_released(&chat.payer, &chat.receiver, &chat.payer, chat.started_at);
+        self.record_released_volume(offerbook::NEAR_ASSET, remaining, [&chat.payer, &chat.receiver]);
         // self.trades.push(&trade);
-        self.create_revenue(
+        self.record_revenue(
           "near".to_string(),
           "trade".to_string(),
           chat.clone().payer.clone(),
-          chat.clone().trade_cost.clone(),
-          chat.clone().trade_cost_usd.clone(),
+          fee,
+          fee_usd,
         );
         self
           .get_account(chat.clone().payer.clone())
           .unwrap()
-          .release(chat.clone().amount.clone(), chat.clone().receiver)
+          .release(remaining, chat.clone().receiver)
       }
     } else {
       // let trade = Trade::new(
@@ -367,69 +550,80 @@ impl Contract {
       //   Some(env::block_timestamp()),
       // );
       if chat.clone().released {
-        self.chats.insert(&chat_id.clone(), &chat.clone());
+        self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
         panic!("Amount already released");
       } else {
         chat.mark_as_released();
         chat.update_ended_at();
-        self.chats.insert(&chat_id.clone(), &chat.clone());
+        self.chats.insert(&chat_id.clone(), &VersionedChat::from(chat.clone()));
+        self.record_chat_released(&chat.payer, &chat.receiver, &chat.receiver, chat.started_at);
+        self.record_released_volume(offerbook::NEAR_ASSET, remaining, [&chat.payer, &chat.receiver]);
         // self.trades.push(&trade);
-        self.create_revenue(
+        self.record_revenue(
           "near".to_string(),
           "trade".to_string(),
           chat.clone().receiver.clone(),
-          chat.clone().trade_cost.clone(),
-          chat.clone().trade_cost_usd.clone(),
+          fee,
+          fee_usd,
         );
         self
           .get_account(chat.clone().receiver.clone())
           .unwrap()
-          .release(chat.clone().amount, chat.payer)
+          .release(remaining, chat.payer)
       }
     }
   }
 
-  pub fn receiver_rate_chat(&mut self, chat_id: String, rating: bool) {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
-    let account_id = env::predecessor_account_id();
-    if account_id.clone() == chat.clone().receiver {
-      if !chat.clone().receiver_has_rated {
-        if rating.clone() {
-          self
-            .get_account(chat.clone().payer.clone())
-            .unwrap()
-            .add_like();
-        } else {
-          self
-            .get_account(chat.clone().payer.clone())
-            .unwrap()
-            .add_dislike();
-        }
-      }
+  /// Lets the account holding the escrow release part of it as fiat arrives
+  /// in parts. The fee is taken in proportion and the chat completes once the
+  /// whole amount is released, canceling refunds what is left.
+  pub fn release_near_partially(&mut self, chat_id: String, amount: U128) -> String {
+    self.assert_not_emergency();
+    let mut chat = match self.chats.get(&chat_id).map(Chat::from) {
+      Some(chat) => chat,
+      None => return "Chat not found".to_string(),
+    };
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
+    let offer = self.get_offer(chat.offer_id.clone()).unwrap();
+    let (locker, recipient) = escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver);
+    self.assert_escrow_holder(&locker);
+    if !chat.active || chat.released || chat.canceled {
+      return "chat is not active".to_string();
+    }
+    let remaining = chat.amount - chat.released_amount;
+    if amount.0 == 0 || amount.0 > remaining {
+      return "Amount must be between zero and the unreleased amount".to_string();
     }
-    chat.receiver_has_rated = true;
-    self.chats.insert(&chat_id.clone(), &chat);
+    // The last part takes whatever fee is left so rounding never strands any
+    let fee = if amount.0 == remaining {
+      chat.trade_cost - chat.fee_collected
+    } else {
+      proportional_fee(chat.trade_cost, amount.0, chat.amount)
+    };
+    let fee_usd = chat.trade_cost_usd * fee as f64 / chat.trade_cost.max(1) as f64;
+
+    self.get_account(locker.clone()).unwrap().unlock(amount.0);
+    self.move_balance(&locker, &recipient, offerbook::NEAR_ASSET, amount.0);
+    self.record_revenue("near".to_string(), "trade".to_string(), locker.clone(), fee, fee_usd);
+    self.record_released_volume(offerbook::NEAR_ASSET, amount.0, [&chat.payer, &chat.receiver]);
+    chat.released_amount += amount.0;
+    chat.fee_collected += fee;
+    if chat.released_amount < chat.amount {
+      self.chats.insert(&chat_id, &VersionedChat::from(chat));
+      return "Partially released".to_string();
+    }
+    chat.received = true;
+    chat.mark_as_released();
+    chat.update_ended_at();
+    self.chats.insert(&chat_id, &VersionedChat::from(chat.clone()));
+    self.record_chat_released(&chat.payer, &chat.receiver, &locker, chat.started_at);
+    "Released".to_string()
   }
 
-  pub fn payer_rate_chat(&mut self, chat_id: String, rating: bool) {
-    let mut chat = self.chats.remove(&chat_id.clone()).unwrap();
-    let account_id = env::predecessor_account_id();
-    if account_id.clone() == chat.clone().payer {
-      if !chat.clone().payer_has_rated {
-        if rating.clone() {
-          self
-            .get_account(chat.clone().receiver.clone())
-            .unwrap()
-            .add_like();
-        } else {
-          self
-            .get_account(chat.clone().receiver.clone())
-            .unwrap()
-            .add_dislike();
-        }
-      }
-    }
-    chat.payer_has_rated = true;
-    self.chats.insert(&chat_id.clone(), &chat);
+  pub fn get_offer_chats(&self, offer_id: String) -> Vec<Chat> {
+    self
+      .offer_chats
+      .get(&offer_id)
+      .map_or(Vec::new(), |chat_ids| chat_ids.iter().filter_map(|chat_id| self.chats.get(&chat_id).map(Chat::from)).collect())
   }
 }
@@ -24,6 +24,10 @@ pub const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + 20_000_000_00
 
 pub const GAS_FOR_FT_TRANSFER: Gas = Gas(20_000_000_000_000);
 
+pub const GAS_FOR_GET_PRICE_DATA: Gas = Gas(10_000_000_000_000);
+
+pub const GAS_FOR_MIGRATE: Gas = Gas(100_000_000_000_000);
+
 // All the accounts that we may need during testing
 pub fn supercode() -> AccountId {
   "supercode.testnet".to_string().try_into().unwrap()
@@ -0,0 +1,177 @@
+use near_sdk::serde_json::json;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Timestamp,
+};
+
+use crate::chat::escrow_sides;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum DenyReason {
+  Fraud,
+  Chargeback,
+  Sanctions,
+  Investigation,
+  Other,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DenyEntry {
+  pub reason: DenyReason,
+  pub note: Option<String>,
+  pub listed_by: AccountId,
+  pub listed_at: Timestamp,
+}
+
+impl Contract {
+  pub(crate) fn assert_not_denylisted(&self, account_id: &AccountId) {
+    if self.denylist.get(account_id).is_some() {
+      panic!("Account {} is denylisted", account_id)
+    }
+  }
+
+  // Returns an error message when one of the accounts is denylisted
+  pub(crate) fn check_denylisted(&self, accounts: &[&AccountId]) -> Option<String> {
+    accounts
+      .iter()
+      .find(|account_id| self.denylist.get(account_id).is_some())
+      .map(|account_id| format!("Account {} is denylisted", account_id))
+  }
+
+  // Escrow of a chat with a denylisted participant can only be moved by arbiters
+  pub(crate) fn assert_escrow_not_frozen(&self, payer: &AccountId, receiver: &AccountId) {
+    if self.check_denylisted(&[payer, receiver]).is_some() {
+      self.assert_role(Role::Arbiter);
+    }
+  }
+
+  fn assert_escrow_frozen(&self, payer: &AccountId, receiver: &AccountId, settled: bool) {
+    assert!(!settled, "Chat is already settled");
+    assert!(
+      self.check_denylisted(&[payer, receiver]).is_some(),
+      "Escrow is not frozen"
+    );
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Freezes an account and deactivates its offers. Only can be called by
+  /// owner or guardians.
+  pub fn deny_account(&mut self, account_id: AccountId, reason: DenyReason, note: Option<String>) {
+    self.assert_owner_or_guardian();
+    let entry = DenyEntry {
+      reason,
+      note,
+      listed_by: env::predecessor_account_id(),
+      listed_at: env::block_timestamp(),
+    };
+    self.denylist.insert(&account_id, &entry);
+
+    for offer in self.get_account_offers(account_id.clone()) {
+      if offer.active {
+        self.update_offer_status(offer.id, false);
+      }
+    }
+    for offer in self.get_account_token_offers(account_id.clone()) {
+      if offer.active {
+        self.update_token_offer_status(offer.id, false);
+      }
+    }
+    emit_event(
+      "account_denied",
+      json!({ "account_id": account_id, "reason": reason, "by": entry.listed_by }),
+    );
+  }
+
+  /// Lifts the freeze, offers stay inactive until the offerer reactivates them.
+  pub fn allow_account(&mut self, account_id: AccountId) {
+    self.assert_owner_or_guardian();
+    self.denylist.remove(&account_id).expect("Account is not denylisted");
+    emit_event(
+      "account_allowed",
+      json!({ "account_id": account_id, "by": env::predecessor_account_id() }),
+    );
+  }
+
+  /// Settles the escrow of a chat frozen by the denylist. The escrow and fee
+  /// are unlocked for the account that locked them, releasing then moves the
+  /// escrow to the counterparty's balance in the contract, the fee is waived.
+  /// Only can be called by arbiters.
+  pub fn resolve_frozen_chat(&mut self, chat_id: String, release: bool) -> String {
+    self.assert_role(Role::Arbiter);
+    let mut chat = self.chats.get(&chat_id).map(Chat::from).expect("Chat not found");
+    self.assert_escrow_frozen(&chat.payer, &chat.receiver, chat.released || chat.canceled);
+    let offer = self.get_offer(chat.offer_id.clone()).unwrap();
+    let (locker, recipient) = escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver);
+    let remaining = chat.amount - chat.released_amount;
+    let fee = chat.trade_cost - chat.fee_collected;
+
+    self.get_account(locker.clone()).unwrap().unlock(remaining + fee);
+    if release {
+      self.move_balance(&locker, &recipient, offerbook::NEAR_ASSET, remaining);
+      let fee_collected = chat.fee_collected;
+      chat.mark_as_released();
+      chat.fee_collected = fee_collected;
+    } else {
+      chat.canceled = true;
+      chat.active = false;
+    }
+    chat.update_ended_at();
+    self.chats.insert(&chat_id, &VersionedChat::from(chat));
+    emit_event(
+      "frozen_chat_resolved",
+      json!({ "chat_id": chat_id, "released": release, "by": env::predecessor_account_id() }),
+    );
+    if release { "chat released" } else { "chat refunded" }.to_string()
+  }
+
+  /// Same as resolve_frozen_chat for token chats, the tokens move between the
+  /// token balances in the contract.
+  pub fn resolve_frozen_token_chat(&mut self, chat_id: String, release: bool) -> String {
+    self.assert_role(Role::Arbiter);
+    let mut chat = self.tokenchats.get(&chat_id).map(TokenChat::from).expect("Chat not found");
+    self.assert_escrow_frozen(&chat.payer, &chat.receiver, chat.released || chat.canceled);
+    let offer = self.get_token_offer(chat.offer_id.clone()).unwrap();
+    let (locker, recipient) = escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver);
+    let remaining = chat.amount - chat.released_amount;
+    let fee = chat.trade_cost - chat.fee_collected;
+
+    let mut acc = self.get_account(locker.clone()).unwrap();
+    acc.unlock_tokens(chat.token_id.clone(), remaining);
+    acc.unlock(fee);
+    drop(acc);
+    if release {
+      self.move_balance(&locker, &recipient, chat.token_id.as_str(), remaining);
+      let fee_collected = chat.fee_collected;
+      chat.mark_as_released();
+      chat.fee_collected = fee_collected;
+    } else {
+      chat.canceled = true;
+      chat.active = false;
+    }
+    chat.update_ended_at();
+    self.tokenchats.insert(&chat_id, &VersionedTokenChat::from(chat));
+    emit_event(
+      "frozen_chat_resolved",
+      json!({ "chat_id": chat_id, "released": release, "by": env::predecessor_account_id() }),
+    );
+    if release { "chat released" } else { "chat refunded" }.to_string()
+  }
+
+  pub fn is_denylisted(&self, account_id: AccountId) -> bool {
+    self.denylist.get(&account_id).is_some()
+  }
+
+  pub fn get_deny_entry(&self, account_id: AccountId) -> Option<DenyEntry> {
+    self.denylist.get(&account_id)
+  }
+
+  pub fn get_denylist(&self) -> Vec<(AccountId, DenyEntry)> {
+    self.denylist.to_vec()
+  }
+}
@@ -0,0 +1,143 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, ONE_NEAR,
+};
+
+use crate::*;
+
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+pub const VOLUME_WINDOW_DAYS: u64 = 30;
+
+// Discount for accounts whose 30 day volume in the asset reaches min_volume
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTier {
+  pub min_volume: u64, // Whole units of the asset
+  pub discount_bps: u32,
+}
+
+pub fn default_fee_schedule() -> Vec<FeeTier> {
+  vec![
+    FeeTier { min_volume: 0, discount_bps: 0 },
+    FeeTier { min_volume: 1_000, discount_bps: 1_000 },
+    FeeTier { min_volume: 10_000, discount_bps: 2_500 },
+    FeeTier { min_volume: 100_000, discount_bps: 5_000 },
+  ]
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VolumeBucket {
+  pub day: u64,
+  pub amount: Balance,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTierInfo {
+  pub volume: U128, // Released volume of the last 30 days, smallest unit of the asset
+  pub tier: usize,  // Index into the fee schedule
+  pub discount_bps: u32,
+  pub effective_fee: U128,   // Trade fee in yoctoNear, send_cost after the discount
+}
+
+// Share of `fee` for `part` of `total`, exact unless the product overflows
+pub fn proportional_fee(fee: Balance, part: Balance, total: Balance) -> Balance {
+  match fee.checked_mul(part) {
+    Some(product) => product / total,
+    None => (fee as f64 * part as f64 / total as f64) as Balance,
+  }
+}
+
+fn volume_key(account_id: &AccountId, asset: &str) -> String {
+  format!("{}:{}", account_id, asset)
+}
+
+impl Contract {
+  pub(crate) fn record_released_volume(&mut self, asset: &str, amount: Balance, accounts: [&AccountId; 2]) {
+    let today = env::block_timestamp() / DAY_NS;
+    for account_id in accounts {
+      let key = volume_key(account_id, asset);
+      let mut buckets = self.volumes.get(&key).unwrap_or_default();
+      buckets.retain(|bucket| bucket.day + VOLUME_WINDOW_DAYS > today);
+      match buckets.iter_mut().find(|bucket| bucket.day == today) {
+        Some(bucket) => bucket.amount += amount,
+        None => buckets.push(VolumeBucket { day: today, amount }),
+      }
+      self.volumes.insert(&key, &buckets);
+    }
+  }
+
+  pub(crate) fn rolling_volume(&self, account_id: &AccountId, asset: &str) -> Balance {
+    let today = env::block_timestamp() / DAY_NS;
+    self
+      .volumes
+      .get(&volume_key(account_id, asset))
+      .unwrap_or_default()
+      .iter()
+      .filter(|bucket| bucket.day + VOLUME_WINDOW_DAYS > today)
+      .map(|bucket| bucket.amount)
+      .sum()
+  }
+
+  // Fee after the volume discount of the account paying it
+  pub(crate) fn discounted_fee(&self, fee_payer: &AccountId, asset: &str, fee: Balance) -> Balance {
+    let discount = self.get_fee_tier(fee_payer.clone(), Some(asset.to_string())).discount_bps;
+    fee - fee * Balance::from(discount) / 10_000
+  }
+
+  // Near fee of a chat on `asset` for the account locking it, and its usd value
+  pub(crate) fn trade_fee(&self, fee_payer: &AccountId, asset: &str) -> (Balance, f64) {
+    let fee = self.discounted_fee(fee_payer, asset, self.send_fee());
+    let fee_usd = self
+      .oracle_price
+      .as_ref()
+      .map_or(0.0, |price| price.price * fee as f64 / ONE_NEAR as f64);
+    (fee, fee_usd)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Queued, see execute_admin_action. Tiers must start at zero volume and
+  /// be sorted by volume.
+  pub fn set_fee_schedule(&mut self, schedule: Vec<FeeTier>) -> String {
+    assert!(
+      schedule.first().is_some_and(|tier| tier.min_volume == 0),
+      "Fee schedule must start with a zero volume tier"
+    );
+    assert!(
+      schedule.windows(2).all(|tiers| tiers[0].min_volume < tiers[1].min_volume),
+      "Fee tiers must be sorted by volume"
+    );
+    assert!(
+      schedule.iter().all(|tier| tier.discount_bps <= 10_000),
+      "Discount can't be above 100%"
+    );
+    self.queue_admin_action(AdminAction::SetFeeSchedule { schedule })
+  }
+
+  pub fn get_fee_schedule(&self) -> Vec<FeeTier> {
+    self.fee_schedule.clone()
+  }
+
+  pub fn get_fee_tier(&self, account_id: AccountId, asset: Option<String>) -> FeeTierInfo {
+    let asset = asset.unwrap_or_else(|| offerbook::NEAR_ASSET.to_string());
+    let volume = self.rolling_volume(&account_id, &asset);
+    let unit = 10u128.pow(u32::from(self.asset_decimals(&asset)));
+    let tier = self
+      .fee_schedule
+      .iter()
+      .rposition(|tier| volume >= Balance::from(tier.min_volume) * unit)
+      .unwrap_or(0);
+    let discount_bps = self.fee_schedule.get(tier).map_or(0, |tier| tier.discount_bps);
+    FeeTierInfo {
+      volume: U128(volume),
+      tier,
+      discount_bps,
+      effective_fee: U128(self.send_fee() - self.send_fee() * Balance::from(discount_bps) / 10_000),
+    }
+  }
+}
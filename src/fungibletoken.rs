@@ -1,11 +1,10 @@
-use core::panic;
-
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::json_types::U128;
 use near_sdk::serde_json::json;
 use near_sdk::{ext_contract, near_bindgen, Promise, PromiseOrValue, PromiseResult, ONE_YOCTO};
 
 use crate::constants::{GAS_FOR_BASIC_OP, GAS_FOR_FT_TRANSFER};
+use crate::invoice::InvoicePaymentMsg;
 use crate::*;
 
 // #[near_bindgen]
@@ -41,16 +40,13 @@ impl ContractCallBacks for Contract {
     token_id: AccountId,
     amount: U128,
   ) -> PromiseOrValue<U128> {
-    let account = self.get_account(account_id.clone());
-    if account.as_ref().is_some() {
-      account
-        .unwrap()
-        .deposit_tokens(token_id.clone(), u128::from(amount.clone()));
-    } else {
-      self.register_new_account(account_id.clone());
-      let account_ = self.get_account(account_id.clone()).unwrap();
-      account_.deposit_tokens(token_id.clone(), u128::from(amount.clone()));
+    if self.accounts.get(&account_id).is_none() {
+      self.register_new_account(account_id.clone(), None);
     }
+    self
+      .get_account(account_id.clone())
+      .unwrap()
+      .deposit_tokens(token_id.clone(), u128::from(amount.clone()));
     PromiseOrValue::Value(U128(0))
   }
 
@@ -66,15 +62,15 @@ impl ContractCallBacks for Contract {
       "{}",
       ERR9_NOT_ALLOWED
     );
-    let account = self.get_account(account_id.clone());
     match env::promise_result(0) {
       PromiseResult::NotReady => unreachable!(),
-      PromiseResult::Successful(_) => account.unwrap().token_release(token_id, u128::from(amount)),
+      PromiseResult::Successful(_) => self.get_account(account_id).unwrap().token_release(token_id, u128::from(amount)),
       PromiseResult::Failed => {
-        let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
+        let mut chat = self.tokenchats.remove(&chat_id.clone()).map(TokenChat::from).unwrap();
         chat.released = false;
         chat.received = false;
-        self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+        chat.released_amount -= u128::from(amount);
+        self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       }
     }
     PromiseOrValue::Value(U128(0))
@@ -95,10 +91,7 @@ impl ContractCallBacks for Contract {
     match env::promise_result(0) {
       PromiseResult::NotReady => unreachable!(),
       PromiseResult::Successful(_) => account.unwrap().token_release(token, u128::from(amount)),
-      PromiseResult::Failed => {
-        panic!("Transaction failed");
-        // PromiseOrValue::Value(U128(0))
-      }
+      PromiseResult::Failed => account.unwrap().unlock_tokens(token, u128::from(amount)),
     }
     PromiseOrValue::Value(U128(0))
   }
@@ -116,7 +109,15 @@ impl FungibleTokenReceiver for Contract {
     msg: String,
   ) -> PromiseOrValue<U128> {
     env::log_str(msg.as_str());
+    // Returning the full amount makes the token contract refund the sender
+    if self.pause.is_paused(Subsystem::Deposits) {
+      return PromiseOrValue::Value(amount);
+    }
     let token_id = env::predecessor_account_id();
+    if let Ok(payment) = near_sdk::serde_json::from_str::<InvoicePaymentMsg>(&msg) {
+      let refund = self.pay_invoice_with_tokens(sender_id, token_id, amount.0, payment.invoice_id);
+      return PromiseOrValue::Value(U128(refund));
+    }
     near_sdk::PromiseOrValue::Promise(
       Self::ext(env::current_account_id()).deposit_tokens(sender_id, token_id, amount),
     )
@@ -156,14 +157,21 @@ impl Contract {
     cross_contract_call.then(callback)
   }
 
-  #[private]
+  /// Sends `amount` of the caller's `token` balance to them. The tokens stay
+  /// locked until the transfer settles and are unlocked again if it fails.
   pub fn withdraw_named_asset(
     &mut self,
     token: AccountId,
     amount: U128
   ) -> Promise {
-
+    self.assert_not_paused(Subsystem::TokenWithdrawals);
     let me = env::predecessor_account_id();
+    self.assert_not_denylisted(&me);
+    self
+      .get_account(me.clone())
+      .expect("Account not found")
+      .lock_tokens(token.clone(), amount.0);
+    self.use_withdrawal_allowance(&me, token.as_str(), amount.0);
 
     let cross_contract_call = Promise::new(token.clone()).function_call(
       "ft_transfer".to_string(),
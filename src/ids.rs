@@ -0,0 +1,47 @@
+use crate::*;
+
+// Prefixes of the ids allocated by the contract, one counter per kind
+pub const OFFER_ID_PREFIX: &str = "offer";
+pub const TOKEN_OFFER_ID_PREFIX: &str = "toffer";
+pub const CHAT_ID_PREFIX: &str = "chat";
+pub const TOKEN_CHAT_ID_PREFIX: &str = "tchat";
+pub const TRANSFER_ID_PREFIX: &str = "transfer";
+pub const TRADE_ID_PREFIX: &str = "trade";
+pub const ADMIN_ACTION_ID_PREFIX: &str = "action";
+pub const INVOICE_ID_PREFIX: &str = "invoice";
+pub const SCHEDULE_ID_PREFIX: &str = "schedule";
+pub const ESCROW_ID_PREFIX: &str = "escrow";
+pub const NEGOTIATION_ID_PREFIX: &str = "negotiation";
+
+impl Contract {
+  pub(crate) fn next_id(&mut self, prefix: &str) -> String {
+    let counter = self.id_counters.get(&prefix.to_string()).unwrap_or(0) + 1;
+    self.id_counters.insert(&prefix.to_string(), &counter);
+    format!("{}-{}", prefix, counter)
+  }
+
+  /// Allocates a new id, or validates the one supplied by a legacy client.
+  /// Supplied ids must be unused and must not look like a generated one.
+  pub(crate) fn resolve_id(
+    &mut self,
+    prefix: &str,
+    id: Option<String>,
+    taken: impl Fn(&Self, &String) -> bool,
+  ) -> Result<String, String> {
+    match id {
+      None => Ok(self.next_id(prefix)),
+      Some(id) if id.starts_with(&format!("{}-", prefix)) => {
+        Err(format!("Ids starting with {}- are reserved", prefix))
+      }
+      Some(id) if taken(self, &id) => Err(format!("Id {} is already in use", id)),
+      Some(id) => Ok(id),
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn get_id_counter(&self, prefix: String) -> u64 {
+    self.id_counters.get(&prefix).unwrap_or(0)
+  }
+}
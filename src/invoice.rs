@@ -0,0 +1,213 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp,
+};
+
+use crate::ids::INVOICE_ID_PREFIX;
+use crate::offerbook::NEAR_ASSET;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum InvoiceStatus {
+  Open,
+  Paid,
+  Expired,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Invoice {
+  pub id: String,
+  pub creator: AccountId,
+  pub payer: Option<AccountId>, // Anyone can pay when unset
+  pub asset: String,            // near or the token account id
+  pub amount: Balance,
+  pub memo: String,
+  pub expires_at: Timestamp,
+  pub status: InvoiceStatus,
+  pub paid_by: Option<AccountId>,
+  pub transfer_id: Option<String>,
+  pub created_on: Timestamp,
+  pub paid_on: Option<Timestamp>,
+}
+
+// ft_transfer_call msg that pays an invoice instead of depositing the tokens
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InvoicePaymentMsg {
+  pub invoice_id: String,
+}
+
+impl Invoice {
+  // Open invoices past their expiry are reported as expired
+  pub fn current_status(&self) -> InvoiceStatus {
+    if self.status == InvoiceStatus::Open && env::block_timestamp() > self.expires_at {
+      InvoiceStatus::Expired
+    } else {
+      self.status
+    }
+  }
+}
+
+impl Contract {
+  // Returns why `payer` can't pay the invoice in `asset`, if it can't
+  fn check_invoice_payable(&self, invoice: &Invoice, payer: &AccountId, asset: &str) -> Option<String> {
+    match invoice.current_status() {
+      InvoiceStatus::Paid => return Some("Invoice is already paid".to_string()),
+      InvoiceStatus::Expired => return Some("Invoice has expired".to_string()),
+      InvoiceStatus::Open => {}
+    }
+    if &invoice.creator == payer {
+      return Some("You can't pay your own invoice".to_string());
+    }
+    if invoice.payer.as_ref().is_some_and(|invoice_payer| invoice_payer != payer) {
+      return Some("This invoice is for another payer".to_string());
+    }
+    if invoice.asset != asset {
+      return Some(format!("Invoice is payable in {}", invoice.asset));
+    }
+    self.check_denylisted(&[payer, &invoice.creator])
+  }
+
+  // Stores the expired status so listings don't need to compute it
+  fn expire_invoice(&mut self, mut invoice: Invoice) {
+    if invoice.current_status() == InvoiceStatus::Expired && invoice.status == InvoiceStatus::Open {
+      invoice.status = InvoiceStatus::Expired;
+      self.invoices.insert(&invoice.id.clone(), &invoice);
+    }
+  }
+
+  fn settle_invoice(&mut self, mut invoice: Invoice, payer: AccountId, transfer_id: String) {
+    invoice.status = InvoiceStatus::Paid;
+    invoice.paid_by = Some(payer);
+    invoice.transfer_id = Some(transfer_id);
+    invoice.paid_on = Some(env::block_timestamp());
+    self.invoices.insert(&invoice.id.clone(), &invoice);
+  }
+
+  /// Pays an invoice with tokens sent through ft_transfer_call and returns
+  /// the amount to refund to the sender.
+  pub(crate) fn pay_invoice_with_tokens(
+    &mut self,
+    payer: AccountId,
+    token: AccountId,
+    amount: Balance,
+    invoice_id: String,
+  ) -> Balance {
+    let invoice = match self.invoices.get(&invoice_id) {
+      Some(invoice) => invoice,
+      None => {
+        env::log_str("Invoice not found");
+        return amount;
+      }
+    };
+    if let Some(err) = self.check_invoice_payable(&invoice, &payer, token.as_str()) {
+      env::log_str(&err);
+      self.expire_invoice(invoice);
+      return amount;
+    }
+    if amount < invoice.amount {
+      env::log_str("Amount is less than the invoice amount");
+      return amount;
+    }
+    self.register_new_account(invoice.creator.clone(), None);
+    self
+      .get_account(invoice.creator.clone())
+      .unwrap()
+      .deposit_tokens(token, invoice.amount);
+    let refund = amount - invoice.amount;
+    let transfer_id = self.add_transfer(None, payer.clone(), invoice.creator.clone(), invoice.amount);
+    self.settle_invoice(invoice, payer, transfer_id);
+    refund
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Issues an invoice payable in near (asset "near") or a listed token.
+  pub fn create_invoice(
+    &mut self,
+    asset: String,
+    amount: U128,
+    memo: String,
+    expires_in_sec: u64,
+    payer: Option<AccountId>,
+  ) -> String {
+    let creator = env::predecessor_account_id();
+    if self.pub_get_account(creator.clone()).is_none() {
+      return "You must be a registered user to create an invoice".to_string();
+    }
+    if let Some(err) = self.check_denylisted(&[&creator]) {
+      return err;
+    }
+    if asset != NEAR_ASSET && asset.parse().ok().and_then(|token| self.get_token(token)).is_none() {
+      return format!("Token {} is not supported", asset);
+    }
+    if amount.0 == 0 {
+      return "Invoice amount must be greater than zero".to_string();
+    }
+    let id = self.next_id(INVOICE_ID_PREFIX);
+    let invoice = Invoice {
+      id: id.clone(),
+      creator,
+      payer,
+      asset,
+      amount: amount.0,
+      memo,
+      expires_at: env::block_timestamp() + expires_in_sec * 1_000_000_000,
+      status: InvoiceStatus::Open,
+      paid_by: None,
+      transfer_id: None,
+      created_on: env::block_timestamp(),
+      paid_on: None,
+    };
+    self.invoices.insert(&id, &invoice);
+    id
+  }
+
+  /// Pays an invoice from the caller's internal balance.
+  pub fn pay_invoice(&mut self, invoice_id: String) -> String {
+    self.assert_not_emergency();
+    let payer = env::predecessor_account_id();
+    let invoice = match self.invoices.get(&invoice_id) {
+      Some(invoice) => invoice,
+      None => return "Invoice not found".to_string(),
+    };
+    if let Some(err) = self.check_invoice_payable(&invoice, &payer, &invoice.asset) {
+      self.expire_invoice(invoice);
+      return err;
+    }
+    if self.pub_get_account(payer.clone()).is_none() {
+      return "You must be a registered user to pay an invoice".to_string();
+    }
+    if self.free_balance(&payer, &invoice.asset) < invoice.amount {
+      return "You do not have enough balance to pay the invoice".to_string();
+    }
+    let transfer_id = self.move_balance(&payer, &invoice.creator, &invoice.asset, invoice.amount);
+    self.settle_invoice(invoice, payer, transfer_id);
+    "Invoice paid".to_string()
+  }
+
+  pub fn get_invoice(&self, invoice_id: String) -> Option<Invoice> {
+    self.invoices.get(&invoice_id).map(|mut invoice| {
+      invoice.status = invoice.current_status();
+      invoice
+    })
+  }
+
+  // Invoices created by or addressed to the account
+  pub fn get_account_invoices(&self, account_id: AccountId) -> Vec<Invoice> {
+    self
+      .invoices
+      .values()
+      .filter(|invoice| invoice.creator == account_id || invoice.payer.as_ref() == Some(&account_id))
+      .map(|mut invoice| {
+        invoice.status = invoice.current_status();
+        invoice
+      })
+      .collect()
+  }
+}
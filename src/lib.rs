@@ -1,36 +1,115 @@
-use crate::account::Account;
+use crate::bond::BondConfig;
 use crate::chat::Chat;
+use crate::denylist::DenyEntry;
 use crate::errors::*;
+use crate::invoice::Invoice;
+use crate::schedule::Schedule;
+use crate::milestone::MilestoneEscrow;
+use crate::negotiation::Negotiation;
+use crate::fees::{default_fee_schedule, proportional_fee, FeeTier, VolumeBucket};
+use crate::limits::{DailyUsage, LimitsConfig};
 use crate::offer::Offer;
+use crate::owner::{AdminAction, PendingAdminAction, DEFAULT_ADMIN_DELAY_SEC};
+use crate::oracle::{OracleConfig, OraclePrice};
+use crate::pause::{PauseState, Subsystem};
+use crate::referral::{Referral, ReferralConfig, ReferralStats};
+use crate::roles::Role;
 use crate::tokenswap::TokenSwap;
 use crate::trade::Trade;
 use crate::transfer::Transfer;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{env, near_bindgen, AccountId, BorshStorageKey, Timestamp};
+use near_sdk::{env, near_bindgen, AccountId, BorshStorageKey, CryptoHash, Timestamp};
+use reputation::Rating;
 use revenue::Revenue;
 use std::collections::HashMap;
 use tokenchats::TokenChat;
 use tokenoffers::TokenOffer;
+use upgrade::{PendingMigration, VersionedAccount, VersionedChat, VersionedOffer, VersionedTokenChat, VersionedTokenOffer};
 pub mod account;
+pub mod bond;
 pub mod chat;
 pub mod constants;
+pub mod denylist;
 pub mod errors;
+pub mod fees;
 pub mod fungibletoken;
+pub mod ids;
+pub mod invoice;
+pub mod limits;
+pub mod milestone;
+pub mod negotiation;
 pub mod offer;
+pub mod offerbook;
+pub mod oracle;
 pub mod owner;
+pub mod pause;
+pub mod quicktrade;
+pub mod referral;
+pub mod reputation;
 pub mod revenue;
+pub mod roles;
+pub mod schedule;
 pub mod tests;
 pub mod tokenchats;
 pub mod tokenoffers;
 pub mod tokenswap;
 pub mod trade;
 pub mod transfer;
+pub mod upgrade;
 
+// The variant index is the storage prefix, only ever add variants at the end.
 #[derive(BorshStorageKey, BorshSerialize)]
 pub(crate) enum StorageKey {
   Transfers,
+  Trades,
+  TokenSwaps,
+  Offers,
+  TokenOffers,
+  OfferBook,
+  Chats,
+  TokenChats,
+  Guardians,
+  Roles,
+  PendingAdminActions,
+  Tokens,
+  WhitelistedTokens,
+  PaymentMethods,
+  Revenues,
+  IdCounters,
+  Ratings,
+  OfferChats,
+  OfferChatIds { offer_hash: CryptoHash },
+  TokenOfferChats,
+  TokenOfferChatIds { offer_hash: CryptoHash },
+  DailyUsage,
+  Denylist,
+  Referrals,
+  ReferralStats,
+  Volumes,
+  Invoices,
+  Schedules,
+  MilestoneEscrows,
+  Negotiations,
+  ScheduleQueue,
+  Accounts,
+  AccountTokens { account_hash: CryptoHash },
+  AccountLockedTokens { account_hash: CryptoHash },
+}
+
+// Logs a NEP-297 event for indexers
+pub(crate) fn emit_event(event: &str, data: near_sdk::serde_json::Value) {
+  env::log_str(&format!(
+    "EVENT_JSON:{}",
+    near_sdk::serde_json::json!({
+      "standard": "sukumaswap",
+      "version": "1.0.0",
+      "event": event,
+      "data": [data],
+    })
+  ));
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug)]
@@ -50,6 +129,31 @@ pub struct PaymentMethod {
   pub icon: String,
 }
 
+// A payment method accepted on an offer, with its own instructions and limits
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferPayment {
+  pub method: String, // Key into payment_methods
+  pub instructions: String,
+  pub min_amount: U128,
+  pub max_amount: U128,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CompleteOfferPayment {
+  pub method: Option<PaymentMethod>,
+  pub instructions: String,
+  pub min_amount: U128,
+  pub max_amount: U128,
+}
+
+impl OfferPayment {
+  pub fn accepts(&self, amount: u128) -> bool {
+    amount >= self.min_amount.0 && amount <= self.max_amount.0
+  }
+}
+
 
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
@@ -57,22 +161,48 @@ pub struct Contract {
   pub owner_id: AccountId,
   pub proposed_owner_id: AccountId,
   pub guardians: UnorderedSet<AccountId>,
-  pub accounts: HashMap<AccountId, Account>,
+  pub denylist: UnorderedMap<AccountId, DenyEntry>,
+  pub roles: UnorderedMap<Role, Vec<AccountId>>,
+  pub pending_admin_actions: UnorderedMap<String, PendingAdminAction>,
+  pub admin_delay_sec: u64,
+  pub accounts: UnorderedMap<AccountId, VersionedAccount>,
   pub trades: Vector<Trade>,
   pub transfers: Vector<Transfer>,
+  pub invoices: UnorderedMap<String, Invoice>,
+  pub schedules: UnorderedMap<String, Schedule>,
+  pub schedule_queue: TreeMap<Timestamp, Vec<String>>, // next run time -> schedule ids
+  pub milestone_escrows: UnorderedMap<String, MilestoneEscrow>,
+  pub negotiations: UnorderedMap<String, Negotiation>,
   pub tokenswaps: LookupMap<String, TokenSwap>,
-  pub offers: UnorderedMap<String, Offer>,
-  pub tokenoffers: UnorderedMap<String, TokenOffer>,
-  pub chats: UnorderedMap<String, Chat>,
-  pub tokenchats: UnorderedMap<String, TokenChat>,
+  pub offers: UnorderedMap<String, VersionedOffer>,
+  pub tokenoffers: UnorderedMap<String, VersionedTokenOffer>,
+  pub offer_book: UnorderedMap<String, Vec<String>>, // asset:currency -> offer ids
+  pub chats: UnorderedMap<String, VersionedChat>,
+  pub tokenchats: UnorderedMap<String, VersionedTokenChat>,
+  pub offer_chats: LookupMap<String, UnorderedSet<String>>, // offer id -> chat ids
+  pub token_offer_chats: LookupMap<String, UnorderedSet<String>>,
   pub transfer_cost: u32,
-  pub send_cost: f32,
+  pub send_cost: f32, // Flat fee in Near per chat and per batch transfer
+  pub fee_schedule: Vec<FeeTier>,
+  pub volumes: LookupMap<String, Vec<VolumeBucket>>, // account:asset -> daily released volume
   pub tokens: UnorderedMap<AccountId, TokenMetadata>,
   pub whitelistedtokens: UnorderedMap<AccountId, TokenMetadata>,
   pub payment_methods: UnorderedMap<String, PaymentMethod>,
   pub revenue: u128,
   pub revenue_usd: f64,
   pub revenues: UnorderedSet<Revenue>,
+  pub referral_config: ReferralConfig,
+  pub referrals: LookupMap<AccountId, Referral>, // referee -> referrer
+  pub referral_stats: LookupMap<AccountId, ReferralStats>,
+  pub id_counters: LookupMap<String, u64>,
+  pub ratings: Vector<Rating>,
+  pub bond_config: BondConfig,
+  pub limits_config: LimitsConfig,
+  pub daily_usage: LookupMap<String, DailyUsage>, // account:asset -> usage of the day
+  pub pause: PauseState,
+  pub oracle: OracleConfig,
+  pub oracle_price: Option<OraclePrice>,
+  pub migration: Option<PendingMigration>, // baseline records migrate_state has yet to move
 }
 
 impl Default for Contract {
@@ -80,23 +210,49 @@ impl Default for Contract {
     Self {
       owner_id: env::current_account_id(),
       proposed_owner_id: env::current_account_id(),
-      trades: Vector::new(b"a".to_vec()),
+      trades: Vector::new(StorageKey::Trades),
       transfers: Vector::new(StorageKey::Transfers),
-      tokenswaps: LookupMap::new(b"b".to_vec()),
+      invoices: UnorderedMap::new(StorageKey::Invoices),
+      schedules: UnorderedMap::new(StorageKey::Schedules),
+      schedule_queue: TreeMap::new(StorageKey::ScheduleQueue),
+      milestone_escrows: UnorderedMap::new(StorageKey::MilestoneEscrows),
+      negotiations: UnorderedMap::new(StorageKey::Negotiations),
+      tokenswaps: LookupMap::new(StorageKey::TokenSwaps),
       transfer_cost: 0,
       send_cost: 0.05,
-      accounts: HashMap::new(),
-      offers: UnorderedMap::new(b"c".to_vec()),
-      tokenoffers: UnorderedMap::new(b"d".to_vec()),
-      chats: UnorderedMap::new(b"e".to_vec()),
-      tokenchats: UnorderedMap::new(b"f".to_vec()),
-      guardians: UnorderedSet::new(b"g".to_vec()),
-      tokens: UnorderedMap::new(b"h".to_vec()),
-      whitelistedtokens: UnorderedMap::new(b"k".to_vec()),
-      payment_methods: UnorderedMap::new(b"i".to_vec()),
+      fee_schedule: default_fee_schedule(),
+      volumes: LookupMap::new(StorageKey::Volumes),
+      accounts: UnorderedMap::new(StorageKey::Accounts),
+      offers: UnorderedMap::new(StorageKey::Offers),
+      tokenoffers: UnorderedMap::new(StorageKey::TokenOffers),
+      offer_book: UnorderedMap::new(StorageKey::OfferBook),
+      chats: UnorderedMap::new(StorageKey::Chats),
+      tokenchats: UnorderedMap::new(StorageKey::TokenChats),
+      offer_chats: LookupMap::new(StorageKey::OfferChats),
+      token_offer_chats: LookupMap::new(StorageKey::TokenOfferChats),
+      guardians: UnorderedSet::new(StorageKey::Guardians),
+      denylist: UnorderedMap::new(StorageKey::Denylist),
+      roles: UnorderedMap::new(StorageKey::Roles),
+      pending_admin_actions: UnorderedMap::new(StorageKey::PendingAdminActions),
+      admin_delay_sec: DEFAULT_ADMIN_DELAY_SEC,
+      tokens: UnorderedMap::new(StorageKey::Tokens),
+      whitelistedtokens: UnorderedMap::new(StorageKey::WhitelistedTokens),
+      payment_methods: UnorderedMap::new(StorageKey::PaymentMethods),
       revenue: 0,
       revenue_usd: 0.0,
-      revenues: UnorderedSet::new(b"l".to_vec()),
+      revenues: UnorderedSet::new(StorageKey::Revenues),
+      referral_config: ReferralConfig::default(),
+      referrals: LookupMap::new(StorageKey::Referrals),
+      referral_stats: LookupMap::new(StorageKey::ReferralStats),
+      id_counters: LookupMap::new(StorageKey::IdCounters),
+      ratings: Vector::new(StorageKey::Ratings),
+      bond_config: BondConfig::default(),
+      limits_config: LimitsConfig::default(),
+      daily_usage: LookupMap::new(StorageKey::DailyUsage),
+      pause: PauseState::default(),
+      oracle: OracleConfig::default(),
+      oracle_price: None,
+      migration: None,
     }
   }
 }
@@ -105,54 +261,41 @@ impl Default for Contract {
 impl Contract {
   #[init]
   pub fn new() -> Self {
-    Self {
-      owner_id: env::current_account_id(),
-      proposed_owner_id: env::current_account_id(),
-      trades: Vector::new(b"a".to_vec()),
-      transfers: Vector::new(StorageKey::Transfers),
-      tokenswaps: LookupMap::new(b"b".to_vec()),
-      transfer_cost: 0,
-      send_cost: 0.05,
-      accounts: HashMap::new(),
-      offers: UnorderedMap::new(b"c".to_vec()),
-      tokenoffers: UnorderedMap::new(b"d".to_vec()),
-      chats: UnorderedMap::new(b"e".to_vec()),
-      tokenchats: UnorderedMap::new(b"f".to_vec()),
-      guardians: UnorderedSet::new(b"g".to_vec()),
-      tokens: UnorderedMap::new(b"h".to_vec()),
-      whitelistedtokens: UnorderedMap::new(b"k".to_vec()),
-      payment_methods: UnorderedMap::new(b"i".to_vec()),
-      revenue: 0,
-      revenue_usd: 0.0,
-      revenues: UnorderedSet::new(b"l".to_vec()),
-    }
+    Self::default()
   }
 
   pub fn add_payment_method(&mut self, method: PaymentMethod) {
+    self.assert_role(Role::Admin);
     self.payment_methods.insert(&method.name, &method);
   }
 
   pub fn remove_payment_method(&mut self, method_name: String) {
+    self.assert_role(Role::Admin);
     self.payment_methods.remove(&method_name);
   }
 
   pub fn add_token(&mut self, token: AccountId, metadata: TokenMetadata) {
+    self.assert_role(Role::TokenManager);
     self.tokens.insert(&token, &metadata);
   }
 
   pub fn remove_token(&mut self, token: AccountId) {
+    self.assert_role(Role::TokenManager);
     self.tokens.remove(&token);
   }
 
   pub fn add_whitelisted_token(&mut self, token: AccountId, metadata: TokenMetadata) {
+    self.assert_role(Role::TokenManager);
     self.whitelistedtokens.insert(&token, &metadata);
   }
 
-  pub fn remove_whitelisted_token(&mut self, token: AccountId) {
-    self.whitelistedtokens.remove(&token);
+  /// Queued, see execute_admin_action
+  pub fn remove_whitelisted_token(&mut self, token: AccountId) -> String {
+    self.queue_admin_action(AdminAction::RemoveWhitelistedToken { token })
   }
 
   pub fn update_token(&mut self, token: AccountId, metadata: TokenMetadata) {
+    self.assert_role(Role::TokenManager);
     self.tokens.remove(&token.clone());
     self.tokens.insert(&token, &metadata);
   }
@@ -169,8 +312,9 @@ impl Contract {
     return self.send_cost;
   }
 
-  pub fn update_send_cost(&mut self, cost: f32){
-    self.send_cost = cost;
+  /// Queued, see execute_admin_action
+  pub fn update_send_cost(&mut self, cost: f32) -> String {
+    self.queue_admin_action(AdminAction::UpdateSendCost { cost })
   }
 
   pub fn get_payments(&self) -> Vec<PaymentMethod> {
@@ -186,4 +330,35 @@ impl Contract {
   pub fn get_payment(&self, name: String) -> Option<PaymentMethod> {
     self.payment_methods.get(&name)
   }
+
+  // Returns an error message if any of the offer payment methods is invalid
+  pub(crate) fn validate_offer_payments(&self, payments: &[OfferPayment]) -> Option<String> {
+    if payments.is_empty() {
+      return Some("Offer must accept at least one payment method".to_string());
+    }
+    for (i, payment) in payments.iter().enumerate() {
+      if self.payment_methods.get(&payment.method).is_none() {
+        return Some(format!("Payment method {} is not supported", payment.method));
+      }
+      if payments[..i].iter().any(|p| p.method == payment.method) {
+        return Some(format!("Payment method {} is listed more than once", payment.method));
+      }
+      if payment.min_amount.0 > payment.max_amount.0 {
+        return Some(format!("Invalid limits for payment method {}", payment.method));
+      }
+    }
+    None
+  }
+
+  pub(crate) fn complete_offer_payments(&self, payments: &[OfferPayment]) -> Vec<CompleteOfferPayment> {
+    payments
+      .iter()
+      .map(|payment| CompleteOfferPayment {
+        method: self.get_payment(payment.method.clone()),
+        instructions: payment.instructions.clone(),
+        min_amount: payment.min_amount,
+        max_amount: payment.max_amount,
+      })
+      .collect()
+  }
 }
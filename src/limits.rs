@@ -0,0 +1,188 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance,
+};
+
+use crate::*;
+
+const DAY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Tier {
+  New,
+  Established,
+  VerifiedMerchant,
+}
+
+// Caps in whole units of the asset (Near or token), converted with its decimals
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TierLimits {
+  pub max_trade: u64,
+  pub daily_trade_volume: u64,
+  pub daily_withdrawal: u64,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LimitsConfig {
+  pub established_min_age_sec: u64,
+  pub established_min_trades: u32,
+  pub new: TierLimits,
+  pub established: TierLimits,
+  pub verified_merchant: TierLimits, // Accounts with the verified merchant bond
+}
+
+impl Default for LimitsConfig {
+  fn default() -> Self {
+    Self {
+      established_min_age_sec: 30 * 24 * 60 * 60,
+      established_min_trades: 10,
+      new: TierLimits {
+        max_trade: 10,
+        daily_trade_volume: 50,
+        daily_withdrawal: 50,
+      },
+      established: TierLimits {
+        max_trade: 100,
+        daily_trade_volume: 500,
+        daily_withdrawal: 500,
+      },
+      verified_merchant: TierLimits {
+        max_trade: 1_000,
+        daily_trade_volume: 5_000,
+        daily_withdrawal: 5_000,
+      },
+    }
+  }
+}
+
+impl LimitsConfig {
+  pub fn for_tier(&self, tier: Tier) -> &TierLimits {
+    match tier {
+      Tier::New => &self.new,
+      Tier::Established => &self.established,
+      Tier::VerifiedMerchant => &self.verified_merchant,
+    }
+  }
+}
+
+// Traded and withdrawn amounts of one account and asset on one day
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DailyUsage {
+  pub day: u64,
+  pub traded: Balance,
+  pub withdrawn: Balance,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Allowance {
+  pub tier: Tier,
+  pub max_trade: U128,
+  pub trade_remaining: U128,
+  pub withdrawal_remaining: U128,
+}
+
+fn usage_key(account_id: &AccountId, asset: &str) -> String {
+  format!("{}:{}", account_id, asset)
+}
+
+impl Contract {
+  // Usage of today, a usage recorded on an earlier day starts over at zero
+  fn daily_usage(&self, account_id: &AccountId, asset: &str) -> DailyUsage {
+    let today = env::block_timestamp() / DAY_NS;
+    match self.daily_usage.get(&usage_key(account_id, asset)) {
+      Some(usage) if usage.day == today => usage,
+      _ => DailyUsage {
+        day: today,
+        ..Default::default()
+      },
+    }
+  }
+
+  fn whole_units(&self, asset: &str, amount: u64) -> Balance {
+    Balance::from(amount) * 10u128.pow(u32::from(self.asset_decimals(asset)))
+  }
+
+  // Returns why the trade is above the limits of one of its participants, if it is
+  pub(crate) fn check_trade_limits(&self, asset: &str, amount: Balance, accounts: [&AccountId; 2]) -> Option<String> {
+    for account_id in accounts {
+      let allowance = self.get_allowance(account_id.clone(), Some(asset.to_string()));
+      if amount > allowance.max_trade.0 {
+        return Some(format!("Amount is above the per-trade limit of {}", account_id));
+      }
+      if amount > allowance.trade_remaining.0 {
+        return Some(format!("Amount is above the daily trade limit of {}", account_id));
+      }
+    }
+    None
+  }
+
+  pub(crate) fn record_trade_usage(&mut self, asset: &str, amount: Balance, accounts: [&AccountId; 2]) {
+    for account_id in accounts {
+      let mut usage = self.daily_usage(account_id, asset);
+      usage.traded += amount;
+      self.daily_usage.insert(&usage_key(account_id, asset), &usage);
+    }
+  }
+
+  // Panics when the withdrawal is above the daily limit, records it otherwise
+  pub(crate) fn use_withdrawal_allowance(&mut self, account_id: &AccountId, asset: &str, amount: Balance) {
+    let allowance = self.get_allowance(account_id.clone(), Some(asset.to_string()));
+    assert!(
+      amount <= allowance.withdrawal_remaining.0,
+      "Amount is above your daily withdrawal limit"
+    );
+    let mut usage = self.daily_usage(account_id, asset);
+    usage.withdrawn += amount;
+    self.daily_usage.insert(&usage_key(account_id, asset), &usage);
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn set_limits_config(&mut self, config: LimitsConfig) {
+    self.assert_role(Role::Admin);
+    self.limits_config = config;
+  }
+
+  pub fn get_limits_config(&self) -> LimitsConfig {
+    self.limits_config.clone()
+  }
+
+  pub fn get_account_tier(&self, account_id: AccountId) -> Tier {
+    if self.is_verified_merchant(account_id.clone()) {
+      return Tier::VerifiedMerchant;
+    }
+    let established = self.pub_get_account(account_id).is_some_and(|acc| {
+      let age = env::block_timestamp().saturating_sub(acc.created_on) / 1_000_000_000;
+      age >= self.limits_config.established_min_age_sec
+        && acc.reputation.completed >= self.limits_config.established_min_trades
+    });
+    if established {
+      Tier::Established
+    } else {
+      Tier::New
+    }
+  }
+
+  /// Remaining allowance of today for near (default) or a token, in the
+  /// smallest unit of the asset.
+  pub fn get_allowance(&self, account_id: AccountId, asset: Option<String>) -> Allowance {
+    let asset = asset.unwrap_or_else(|| offerbook::NEAR_ASSET.to_string());
+    let tier = self.get_account_tier(account_id.clone());
+    let limits = self.limits_config.for_tier(tier);
+    let usage = self.daily_usage(&account_id, &asset);
+    Allowance {
+      tier,
+      max_trade: U128(self.whole_units(&asset, limits.max_trade)),
+      trade_remaining: U128(self.whole_units(&asset, limits.daily_trade_volume).saturating_sub(usage.traded)),
+      withdrawal_remaining: U128(self.whole_units(&asset, limits.daily_withdrawal).saturating_sub(usage.withdrawn)),
+    }
+  }
+}
@@ -0,0 +1,330 @@
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp,
+};
+
+use crate::ids::ESCROW_ID_PREFIX;
+use crate::offerbook::NEAR_ASSET;
+use crate::*;
+
+pub const MAX_MILESTONES: usize = 20;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MilestoneStatus {
+  Funded,
+  Submitted,
+  Released,
+  Refunded,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EscrowStatus {
+  Active,
+  Disputed,
+  Completed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneInput {
+  pub description: String,
+  pub amount: U128,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Milestone {
+  pub description: String,
+  pub amount: Balance,
+  pub status: MilestoneStatus,
+  pub submitted_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MilestoneEscrow {
+  pub id: String,
+  pub client: AccountId,
+  pub provider: AccountId,
+  pub asset: String, // near or the token account id
+  pub milestones: Vec<Milestone>,
+  pub review_sec: u64, // After a submission the provider can claim the tranche once this passes
+  pub deadline: Timestamp, // After it the client can take back milestones never submitted
+  pub status: EscrowStatus,
+  pub disputed_by: Option<AccountId>,
+  pub created_on: Timestamp,
+}
+
+impl MilestoneEscrow {
+  fn is_settled(&self) -> bool {
+    self
+      .milestones
+      .iter()
+      .all(|milestone| matches!(milestone.status, MilestoneStatus::Released | MilestoneStatus::Refunded))
+  }
+}
+
+impl Contract {
+  fn lock_asset(&mut self, account_id: &AccountId, asset: &str, amount: Balance) {
+    let mut acc = self.get_account(account_id.clone()).expect("Account not found");
+    if asset == NEAR_ASSET {
+      acc.lock(amount);
+    } else {
+      acc.lock_tokens(asset.parse().unwrap(), amount);
+    }
+  }
+
+  fn unlock_asset(&mut self, account_id: &AccountId, asset: &str, amount: Balance) {
+    let mut acc = self.get_account(account_id.clone()).expect("Account not found");
+    if asset == NEAR_ASSET {
+      acc.unlock(amount);
+    } else {
+      acc.unlock_tokens(asset.parse().unwrap(), amount);
+    }
+  }
+
+  // Pays the milestone's tranche out of the client's locked balance
+  fn release_milestone(&mut self, escrow: &mut MilestoneEscrow, index: usize) {
+    let amount = escrow.milestones[index].amount;
+    self.unlock_asset(&escrow.client, &escrow.asset, amount);
+    self.move_balance(&escrow.client, &escrow.provider, &escrow.asset, amount);
+    escrow.milestones[index].status = MilestoneStatus::Released;
+  }
+
+  fn refund_milestone(&mut self, escrow: &mut MilestoneEscrow, index: usize) {
+    self.unlock_asset(&escrow.client, &escrow.asset, escrow.milestones[index].amount);
+    escrow.milestones[index].status = MilestoneStatus::Refunded;
+  }
+
+  fn save_escrow(&mut self, mut escrow: MilestoneEscrow) {
+    if escrow.is_settled() {
+      escrow.status = EscrowStatus::Completed;
+    }
+    self.milestone_escrows.insert(&escrow.id.clone(), &escrow);
+  }
+
+  // Returns the escrow when it exists, is active and has the milestone
+  fn active_escrow(&self, escrow_id: &str, index: usize) -> Result<MilestoneEscrow, String> {
+    let escrow = self.milestone_escrows.get(&escrow_id.to_string()).ok_or("Escrow not found")?;
+    if escrow.status != EscrowStatus::Active {
+      return Err("Escrow is not active".to_string());
+    }
+    if index >= escrow.milestones.len() {
+      return Err("Milestone not found".to_string());
+    }
+    self.assert_escrow_not_frozen(&escrow.client, &escrow.provider);
+    Ok(escrow)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Locks the total of the milestones from the caller's balance until each
+  /// tranche is released to the provider or refunded.
+  pub fn create_milestone_escrow(
+    &mut self,
+    provider: AccountId,
+    asset: String,
+    milestones: Vec<MilestoneInput>,
+    review_sec: u64,
+    deadline_in_sec: u64,
+  ) -> String {
+    self.assert_not_paused(Subsystem::Chats);
+    let client = env::predecessor_account_id();
+    if self.pub_get_account(client.clone()).is_none() {
+      return "You must be a registered user to create an escrow".to_string();
+    }
+    if self.pub_get_account(provider.clone()).is_none() {
+      return format!("Provider {} is not registered", provider);
+    }
+    if provider == client {
+      return "You can't hire yourself".to_string();
+    }
+    if let Some(err) = self.check_denylisted(&[&client, &provider]) {
+      return err;
+    }
+    if asset != NEAR_ASSET && asset.parse().ok().and_then(|token| self.get_token(token)).is_none() {
+      return format!("Token {} is not supported", asset);
+    }
+    if milestones.is_empty() || milestones.len() > MAX_MILESTONES {
+      return format!("An escrow needs between 1 and {} milestones", MAX_MILESTONES);
+    }
+    if milestones.iter().any(|milestone| milestone.amount.0 == 0) {
+      return "Milestone amounts must be greater than zero".to_string();
+    }
+    let total: Balance = milestones.iter().map(|milestone| milestone.amount.0).sum();
+    if self.free_balance(&client, &asset) < total {
+      return "You do not have enough balance to fund the milestones".to_string();
+    }
+
+    self.lock_asset(&client, &asset, total);
+    let id = self.next_id(ESCROW_ID_PREFIX);
+    let escrow = MilestoneEscrow {
+      id: id.clone(),
+      client,
+      provider,
+      asset,
+      milestones: milestones
+        .into_iter()
+        .map(|milestone| Milestone {
+          description: milestone.description,
+          amount: milestone.amount.0,
+          status: MilestoneStatus::Funded,
+          submitted_at: None,
+        })
+        .collect(),
+      review_sec,
+      deadline: env::block_timestamp() + deadline_in_sec * 1_000_000_000,
+      status: EscrowStatus::Active,
+      disputed_by: None,
+      created_on: env::block_timestamp(),
+    };
+    self.milestone_escrows.insert(&id, &escrow);
+    id
+  }
+
+  pub fn submit_milestone(&mut self, escrow_id: String, index: usize) -> String {
+    let mut escrow = match self.active_escrow(&escrow_id, index) {
+      Ok(escrow) => escrow,
+      Err(err) => return err,
+    };
+    if escrow.provider != env::predecessor_account_id() {
+      return "Only the provider can submit milestones".to_string();
+    }
+    if escrow.milestones[index].status != MilestoneStatus::Funded {
+      return "Milestone is not open".to_string();
+    }
+    escrow.milestones[index].status = MilestoneStatus::Submitted;
+    escrow.milestones[index].submitted_at = Some(env::block_timestamp());
+    self.save_escrow(escrow);
+    "Milestone submitted".to_string()
+  }
+
+  /// Client approval, releases the tranche to the provider.
+  pub fn approve_milestone(&mut self, escrow_id: String, index: usize) -> String {
+    self.assert_not_emergency();
+    let mut escrow = match self.active_escrow(&escrow_id, index) {
+      Ok(escrow) => escrow,
+      Err(err) => return err,
+    };
+    if escrow.client != env::predecessor_account_id() {
+      return "Only the client can approve milestones".to_string();
+    }
+    if escrow.milestones[index].status != MilestoneStatus::Submitted {
+      return "Milestone is not submitted".to_string();
+    }
+    self.release_milestone(&mut escrow, index);
+    self.save_escrow(escrow);
+    "Milestone released".to_string()
+  }
+
+  /// Lets the provider take a submitted tranche the client didn't review in time.
+  pub fn claim_milestone(&mut self, escrow_id: String, index: usize) -> String {
+    self.assert_not_emergency();
+    let mut escrow = match self.active_escrow(&escrow_id, index) {
+      Ok(escrow) => escrow,
+      Err(err) => return err,
+    };
+    if escrow.provider != env::predecessor_account_id() {
+      return "Only the provider can claim milestones".to_string();
+    }
+    let review_ends = match escrow.milestones[index].submitted_at {
+      Some(submitted_at) if escrow.milestones[index].status == MilestoneStatus::Submitted => {
+        submitted_at + escrow.review_sec * 1_000_000_000
+      }
+      _ => return "Milestone is not submitted".to_string(),
+    };
+    if env::block_timestamp() <= review_ends {
+      return "Milestone is still in review".to_string();
+    }
+    self.release_milestone(&mut escrow, index);
+    self.save_escrow(escrow);
+    "Milestone released".to_string()
+  }
+
+  /// After the deadline the client takes back every milestone never submitted.
+  pub fn refund_unsubmitted_milestones(&mut self, escrow_id: String) -> String {
+    self.assert_not_emergency();
+    let mut escrow = match self.active_escrow(&escrow_id, 0) {
+      Ok(escrow) => escrow,
+      Err(err) => return err,
+    };
+    if escrow.client != env::predecessor_account_id() {
+      return "Only the client can refund milestones".to_string();
+    }
+    if env::block_timestamp() <= escrow.deadline {
+      return "The escrow deadline has not passed".to_string();
+    }
+    for index in 0..escrow.milestones.len() {
+      if escrow.milestones[index].status == MilestoneStatus::Funded {
+        self.refund_milestone(&mut escrow, index);
+      }
+    }
+    self.save_escrow(escrow);
+    "Milestones refunded".to_string()
+  }
+
+  /// Freezes the escrow until an arbiter or guardian resolves its milestones.
+  pub fn dispute_milestone_escrow(&mut self, escrow_id: String) -> String {
+    let mut escrow = match self.active_escrow(&escrow_id, 0) {
+      Ok(escrow) => escrow,
+      Err(err) => return err,
+    };
+    let caller = env::predecessor_account_id();
+    if caller != escrow.client && caller != escrow.provider {
+      return "Only the client or provider can dispute the escrow".to_string();
+    }
+    escrow.status = EscrowStatus::Disputed;
+    escrow.disputed_by = Some(caller.clone());
+    self.save_escrow(escrow);
+    emit_event("escrow_disputed", json!({ "escrow_id": escrow_id, "by": caller }));
+    "Escrow disputed".to_string()
+  }
+
+  /// Arbiters and guardians settle the open milestones of a disputed escrow,
+  /// each to the provider or back to the client. Active escrows with a
+  /// denylisted party are frozen and settled here too.
+  pub fn resolve_milestone_dispute(&mut self, escrow_id: String, release_to_provider: Vec<bool>) {
+    self.assert_role_or_guardian(Role::Arbiter);
+    let mut escrow = self.milestone_escrows.get(&escrow_id).expect("Escrow not found");
+    let frozen = self.check_denylisted(&[&escrow.client, &escrow.provider]).is_some();
+    assert!(
+      escrow.status == EscrowStatus::Disputed || (escrow.status == EscrowStatus::Active && frozen),
+      "Escrow is not disputed"
+    );
+    assert!(
+      release_to_provider.len() == escrow.milestones.len(),
+      "Give a decision for every milestone"
+    );
+    for (index, release) in release_to_provider.into_iter().enumerate() {
+      match escrow.milestones[index].status {
+        MilestoneStatus::Funded | MilestoneStatus::Submitted if release => self.release_milestone(&mut escrow, index),
+        MilestoneStatus::Funded | MilestoneStatus::Submitted => self.refund_milestone(&mut escrow, index),
+        _ => {}
+      }
+    }
+    self.save_escrow(escrow);
+    emit_event(
+      "escrow_resolved",
+      json!({ "escrow_id": escrow_id, "by": env::predecessor_account_id() }),
+    );
+  }
+
+  pub fn get_milestone_escrow(&self, escrow_id: String) -> Option<MilestoneEscrow> {
+    self.milestone_escrows.get(&escrow_id)
+  }
+
+  // Escrows where the account is the client or the provider
+  pub fn get_account_milestone_escrows(&self, account_id: AccountId) -> Vec<MilestoneEscrow> {
+    self
+      .milestone_escrows
+      .values()
+      .filter(|escrow| escrow.client == account_id || escrow.provider == account_id)
+      .collect()
+  }
+}
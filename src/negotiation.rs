@@ -0,0 +1,207 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp,
+};
+
+use crate::ids::NEGOTIATION_ID_PREFIX;
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum NegotiationStatus {
+  Open,
+  Accepted,
+  Canceled,
+}
+
+// Terms of a chat the opener and the offerer are still agreeing on, nothing
+// is locked until one accepts the other's latest proposal
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Negotiation {
+  pub id: String,
+  pub offer_id: String,
+  pub opener: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub rate: f32,
+  pub proposed_by: AccountId, // The other side accepts or counters
+  pub payment_method: String,
+  pub payment_msg: String,
+  pub status: NegotiationStatus,
+  pub chat_id: Option<String>,
+  pub created_on: Timestamp,
+  pub updated_on: Timestamp,
+}
+
+impl Contract {
+  // Returns the open negotiation when the caller is the side that has to answer
+  fn negotiation_to_answer(&self, negotiation_id: &str) -> Result<Negotiation, String> {
+    let negotiation = self
+      .negotiations
+      .get(&negotiation_id.to_string())
+      .ok_or("Negotiation not found")?;
+    if negotiation.status != NegotiationStatus::Open {
+      return Err("Negotiation is closed".to_string());
+    }
+    let caller = env::predecessor_account_id();
+    if caller != negotiation.opener && caller != negotiation.offerer {
+      return Err("You are not part of this negotiation".to_string());
+    }
+    if caller == negotiation.proposed_by {
+      return Err("Wait for the other side to answer your proposal".to_string());
+    }
+    Ok(negotiation)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Proposes a chat on the offer at another rate or amount than listed.
+  /// Only near offers can be negotiated, token chats trade at the listed rate.
+  pub fn propose_rate(
+    &mut self,
+    offer_id: String,
+    amount: U128,
+    rate: f32,
+    payment_method: String,
+    payment_msg: String,
+  ) -> String {
+    self.assert_not_paused(Subsystem::Chats);
+    let opener = env::predecessor_account_id();
+    if self.get_token_offer(offer_id.clone()).is_some() {
+      return "Token offers can't be negotiated".to_string();
+    }
+    let offer = match self.get_offer(offer_id.clone()) {
+      Some(offer) if offer.active => offer,
+      _ => return "Offer not found".to_string(),
+    };
+    if self.pub_get_account(opener.clone()).is_none() {
+      return "You must be a registered user to chat with someone".to_string();
+    }
+    if opener == offer.offerer {
+      return "You can't chat with yourself".to_string();
+    }
+    if let Some(err) = self.check_denylisted(&[&opener, &offer.offerer]) {
+      return err;
+    }
+    if offer.get_payment(&payment_method).is_none() {
+      return "Payment method is not accepted by this offer".to_string();
+    }
+    if rate <= 0.0 || amount.0 == 0 {
+      return "Rate and amount must be greater than zero".to_string();
+    }
+    let id = self.next_id(NEGOTIATION_ID_PREFIX);
+    let negotiation = Negotiation {
+      id: id.clone(),
+      offer_id,
+      opener: opener.clone(),
+      offerer: offer.offerer,
+      amount: amount.0,
+      rate,
+      proposed_by: opener,
+      payment_method,
+      payment_msg,
+      status: NegotiationStatus::Open,
+      chat_id: None,
+      created_on: env::block_timestamp(),
+      updated_on: env::block_timestamp(),
+    };
+    self.negotiations.insert(&id, &negotiation);
+    id
+  }
+
+  /// Answers the latest proposal with other terms.
+  pub fn counter_rate(&mut self, negotiation_id: String, amount: U128, rate: f32) -> String {
+    let mut negotiation = match self.negotiation_to_answer(&negotiation_id) {
+      Ok(negotiation) => negotiation,
+      Err(err) => return err,
+    };
+    if rate <= 0.0 || amount.0 == 0 {
+      return "Rate and amount must be greater than zero".to_string();
+    }
+    negotiation.amount = amount.0;
+    negotiation.rate = rate;
+    negotiation.proposed_by = env::predecessor_account_id();
+    negotiation.updated_on = env::block_timestamp();
+    self.negotiations.insert(&negotiation_id, &negotiation);
+    "Counter proposed".to_string()
+  }
+
+  /// Accepts the latest proposal and opens the chat with the agreed terms,
+  /// locking the escrow like add_buy_chat/add_sell_chat do. Returns the chat
+  /// id or why it couldn't be opened.
+  pub fn accept_rate(&mut self, negotiation_id: String) -> String {
+    let mut negotiation = match self.negotiation_to_answer(&negotiation_id) {
+      Ok(negotiation) => negotiation,
+      Err(err) => return err,
+    };
+    let offer = match self.get_offer(negotiation.offer_id.clone()) {
+      Some(offer) if offer.active => offer,
+      _ => return "Offer not found".to_string(),
+    };
+    let open_chat = if offer.offer_type == "buy" {
+      Self::open_buy_chat
+    } else {
+      Self::open_sell_chat
+    };
+    let opened = open_chat(
+      self,
+      None,
+      negotiation.offer_id.clone(),
+      negotiation.opener.clone(),
+      U128(negotiation.amount),
+      negotiation.opener.clone(),
+      negotiation.offerer.clone(),
+      negotiation.payment_method.clone(),
+      negotiation.payment_msg.clone(),
+    );
+    let chat_id = match opened {
+      Ok(chat_id) => chat_id,
+      Err(err) => return err,
+    };
+    let mut chat = self.chats.get(&chat_id).map(Chat::from).unwrap();
+    chat.set_agreed_rate(negotiation.rate);
+    self.chats.insert(&chat_id, &VersionedChat::from(chat));
+
+    negotiation.status = NegotiationStatus::Accepted;
+    negotiation.chat_id = Some(chat_id.clone());
+    negotiation.updated_on = env::block_timestamp();
+    self.negotiations.insert(&negotiation_id, &negotiation);
+    chat_id
+  }
+
+  /// Either side walks away, nothing was locked so there is nothing to refund.
+  pub fn cancel_negotiation(&mut self, negotiation_id: String) -> String {
+    let mut negotiation = match self.negotiations.get(&negotiation_id) {
+      Some(negotiation) => negotiation,
+      None => return "Negotiation not found".to_string(),
+    };
+    let caller = env::predecessor_account_id();
+    if caller != negotiation.opener && caller != negotiation.offerer {
+      return "You are not part of this negotiation".to_string();
+    }
+    if negotiation.status != NegotiationStatus::Open {
+      return "Negotiation is closed".to_string();
+    }
+    negotiation.status = NegotiationStatus::Canceled;
+    negotiation.updated_on = env::block_timestamp();
+    self.negotiations.insert(&negotiation_id, &negotiation);
+    "Negotiation canceled".to_string()
+  }
+
+  pub fn get_negotiation(&self, negotiation_id: String) -> Option<Negotiation> {
+    self.negotiations.get(&negotiation_id)
+  }
+
+  // Negotiations the account opened or received on its offers
+  pub fn get_account_negotiations(&self, account_id: AccountId) -> Vec<Negotiation> {
+    self
+      .negotiations
+      .values()
+      .filter(|negotiation| negotiation.opener == account_id || negotiation.offerer == account_id)
+      .collect()
+  }
+}
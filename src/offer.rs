@@ -6,8 +6,20 @@ use near_sdk::{
 };
 
 use crate::account::PubAccountInfo;
+use crate::offerbook::NEAR_ASSET;
+use crate::ids::OFFER_ID_PREFIX;
 use crate::*;
 
+// Who the offerer is willing to trade with, unset fields are not checked
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferRequirements {
+  pub min_completed_trades: Option<u32>,
+  pub min_account_age_sec: Option<u64>,
+  pub min_like_ratio: Option<f32>, // likes / (likes + dislikes), 0.0 - 1.0
+  pub allowlist: Option<Vec<AccountId>>, // Only these accounts can trade when set
+}
+
 // #[near_bindgen]
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
 #[serde(crate = "near_sdk::serde")]
@@ -18,10 +30,13 @@ pub struct Offer {
   pub min_amount: u128,
   pub max_amount: u128,
   pub offer_rate: f32,
+  pub margin: Option<f32>, // Floating offers: percent over the oracle price
   pub active: bool,
-  pub payment: String,
+  pub payments: Vec<OfferPayment>,
   pub currency: String,
   pub instructions: String,
+  pub created_on: Timestamp,
+  pub requirements: OfferRequirements,
 }
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
@@ -33,10 +48,13 @@ pub struct CompleteOffer {
   pub min_amount: u128,
   pub max_amount: u128,
   pub offer_rate: f32,
+  pub margin: Option<f32>,
   pub active: bool,
-  pub payment: Option<PaymentMethod>,
+  pub payments: Vec<CompleteOfferPayment>,
   pub currency: String,
   pub instructions: String,
+  pub created_on: Timestamp,
+  pub requirements: OfferRequirements,
 }
 
 // #[near_bindgen]
@@ -48,7 +66,8 @@ impl Offer {
     min_amount: U128,
     max_amount: U128,
     offer_rate: f32,
-    payment: String,
+    margin: Option<f32>,
+    payments: Vec<OfferPayment>,
     currency: String,
     instructions: String,
   ) -> Self {
@@ -59,10 +78,13 @@ impl Offer {
       min_amount: u128::from(min_amount),
       max_amount: u128::from(max_amount),
       offer_rate,
-      payment,
+      margin,
+      payments,
       currency,
       instructions,
       active: true,
+      created_on: env::block_timestamp(),
+      requirements: OfferRequirements::default(),
     }
   }
 
@@ -74,7 +96,15 @@ impl Offer {
     self.active = active;
   }
 
-  pub fn make_complete_offer(&self, payment: Option<PaymentMethod>) -> CompleteOffer {
+  pub fn get_payment(&self, method: &str) -> Option<&OfferPayment> {
+    self.payments.iter().find(|payment| payment.method == method)
+  }
+
+  pub fn is_floating(&self) -> bool {
+    self.margin.is_some()
+  }
+
+  pub fn make_complete_offer(&self, payments: Vec<CompleteOfferPayment>) -> CompleteOffer {
     return CompleteOffer {
       id: self.id.clone(),
       offer_type: self.offer_type.clone(),
@@ -82,10 +112,13 @@ impl Offer {
       min_amount: self.min_amount.clone(),
       max_amount: self.max_amount,
       offer_rate: self.offer_rate,
+      margin: self.margin,
       active: self.active,
-      payment,
+      payments,
       currency: self.currency.clone(),
       instructions: self.instructions.clone(),
+      created_on: self.created_on,
+      requirements: self.requirements.clone(),
     };
   }
 }
@@ -94,37 +127,57 @@ impl Offer {
 impl Contract {
   pub fn add_offer(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_type: String,
     offerer: AccountId,
     min_amount: U128,
     max_amount: U128,
     offer_rate: f32,
-    payment: String,
+    margin: Option<f32>,
+    payments: Vec<OfferPayment>,
     currency: String,
     instructions: String,
+    requirements: Option<OfferRequirements>,
   ) -> String {
+    self.assert_not_paused(Subsystem::Offers);
+    if let Some(err) = self.check_denylisted(&[&offerer]) {
+      return err;
+    }
+    if let Some(err) = self.validate_offer_payments(&payments) {
+      return err;
+    }
+    if u128::from(max_amount) > self.get_offer_limit(offerer.clone()).0 {
+      return "Offer amount is above your limit, stake a bond to raise it".to_string();
+    }
+    let id = match self.resolve_id(OFFER_ID_PREFIX, id, |c, id| c.offers.get(id).is_some()) {
+      Ok(id) => id,
+      Err(err) => return err,
+    };
     // Check offer type, if buy, don't check account balance
-    let account = self.get_account(offerer.clone()).unwrap();
-    let offer = Offer::new(
+    let account = self.pub_get_account(offerer.clone()).unwrap();
+    let mut offer = Offer::new(
       id.clone(),
       offer_type.clone(),
       offerer,
       min_amount,
       max_amount,
       offer_rate,
-      payment,
+      margin,
+      payments,
       currency,
       instructions,
     );
+    offer.requirements = requirements.unwrap_or_default();
 
     if offer_type.clone() == "buy".to_string() {
-      self.offers.insert(&id, &offer);
-      return "Offer created successfully".to_string();
+      self.index_offer(NEAR_ASSET, &offer.currency, &id);
+      self.offers.insert(&id, &VersionedOffer::from(offer));
+      return id;
     } else {
       if account.balance >= offer.max_amount.clone() {
-        self.offers.insert(&id, &offer);
-        return "Offer created successfully".to_string();
+        self.index_offer(NEAR_ASSET, &offer.currency, &id);
+        self.offers.insert(&id, &VersionedOffer::from(offer));
+        return id;
       } else {
         // panic!("You do not have enough balance to add offer");
         return "You do not have enough balance to add offer".to_string();
@@ -134,7 +187,7 @@ impl Contract {
 
   pub fn get_account_offers(&self, account_id: AccountId) -> Vec<Offer> {
     let mut offers = Vec::new();
-    self.offers.to_vec().into_iter().for_each(|(_id, offer)| {
+    self.offers.values().map(Offer::from).for_each(|offer| {
       if offer.offerer == account_id {
         offers.push(offer)
       }
@@ -147,14 +200,14 @@ impl Contract {
   }
   // Used internally
   pub fn get_offer(&self, offer_id: String) -> Option<Offer> {
-    self.offers.get(&offer_id)
+    self.offers.get(&offer_id).map(Offer::from)
   }
 
   // Used externally from the frontend
   pub fn pub_get_offer(&self, offer_id: String) -> Option<CompleteOffer> {
-    let offer = self.offers.get(&offer_id).unwrap();
-    let payment = self.get_payment(offer.payment.clone());
-    let mut comp_offer = offer.make_complete_offer(payment);
+    let offer = self.offers.get(&offer_id).map(Offer::from).unwrap();
+    let payments = self.complete_offer_payments(&offer.payments);
+    let mut comp_offer = offer.make_complete_offer(payments);
     let offerer = self.acc_pub_info(offer.offerer);
     comp_offer.offerer = offerer;
     Some(comp_offer)
@@ -162,10 +215,10 @@ impl Contract {
 
   pub fn get_buy_offers(&self) -> Vec<CompleteOffer> {
     let mut offers = Vec::new();
-    self.offers.to_vec().into_iter().for_each(|(_id, offer)| {
+    self.offers.values().map(Offer::from).for_each(|offer| {
       if offer.offer_type == "buy".to_string() {
-        let payment = self.get_payment(offer.payment.clone());
-        let mut comp_offer = offer.make_complete_offer(payment);
+        let payments = self.complete_offer_payments(&offer.payments);
+        let mut comp_offer = offer.make_complete_offer(payments);
         let offerer = self.acc_pub_info(offer.offerer);
         comp_offer.offerer = offerer;
         offers.push(comp_offer);
@@ -176,10 +229,10 @@ impl Contract {
 
   pub fn get_sell_offers(&self) -> Vec<CompleteOffer> {
     let mut offers = Vec::new();
-    self.offers.to_vec().into_iter().for_each(|(_id, offer)| {
+    self.offers.values().map(Offer::from).for_each(|offer| {
       if offer.offer_type == "sell".to_string() {
-        let payment = self.get_payment(offer.payment.clone());
-        let mut comp_offer = offer.make_complete_offer(payment);
+        let payments = self.complete_offer_payments(&offer.payments);
+        let mut comp_offer = offer.make_complete_offer(payments);
         let offerer = self.acc_pub_info(offer.offerer);
         comp_offer.offerer = offerer;
         offers.push(comp_offer);
@@ -188,13 +241,46 @@ impl Contract {
     offers
   }
 
+  // Returns why the account does not meet the offer requirements, if it doesn't
+  pub(crate) fn check_offer_requirements(&self, requirements: &OfferRequirements, account_id: AccountId) -> Option<String> {
+    if let Some(allowlist) = &requirements.allowlist {
+      if !allowlist.contains(&account_id) {
+        return Some("The offerer only trades with selected accounts".to_string());
+      }
+    }
+    let acc = match self.pub_get_account(account_id) {
+      Some(acc) => acc,
+      None => return Some("You must be a registered user to chat with someone".to_string()),
+    };
+    if acc.reputation.completed < requirements.min_completed_trades.unwrap_or(0) {
+      return Some("You have not completed enough trades for this offer".to_string());
+    }
+    let age = env::block_timestamp().saturating_sub(acc.created_on) / 1_000_000_000;
+    if age < requirements.min_account_age_sec.unwrap_or(0) {
+      return Some("Your account is too new for this offer".to_string());
+    }
+    if let Some(min_ratio) = requirements.min_like_ratio {
+      let rated = acc.likes + acc.dislikes;
+      let ratio = if rated > 0 { acc.likes as f32 / rated as f32 } else { 0.0 };
+      if ratio < min_ratio {
+        return Some("Your like ratio is too low for this offer".to_string());
+      }
+    }
+    None
+  }
+
   pub fn update_offer_status(&mut self, offer_id: String, active: bool) {
-    let mut offer = self.offers.remove(&offer_id.clone()).unwrap();
+    let mut offer = self.offers.remove(&offer_id.clone()).map(Offer::from).unwrap();
+    if active {
+      self.assert_not_denylisted(&offer.offerer);
+    }
     offer.update_offer_status(active);
-    self.offers.insert(&offer_id, &offer);
+    self.offers.insert(&offer_id, &VersionedOffer::from(offer));
   }
 
   pub fn clear_offers(&mut self) {
+    self.assert_role(Role::Admin);
     self.offers.clear();
+    self.clear_offer_book(true);
   }
 }
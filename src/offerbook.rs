@@ -0,0 +1,227 @@
+use std::cmp::Ordering;
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::offer::CompleteOffer;
+use crate::tokenoffers::CompleteTokenOffer;
+use crate::*;
+
+// Asset key used in the offer book for Near offers, token offers use the token account id
+pub const NEAR_ASSET: &str = "near";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferFilter {
+  pub offer_type: String, // buy or sell
+  pub currency: String,
+  pub payment_method: Option<String>,
+  pub amount: Option<U128>, // Only offers whose limits allow this amount
+  pub min_likes: Option<i32>,
+  pub active_only: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OfferSort {
+  BestRate,
+  Reputation,
+  Newest,
+}
+
+// Values an offer is ranked on once it passed the filter
+pub struct OfferRank {
+  pub rate: Option<f32>,
+  pub score: f32,
+  pub created_on: Timestamp,
+}
+
+pub fn offer_book_key(asset: &str, currency: &str) -> String {
+  format!("{}:{}", asset, currency)
+}
+
+impl OfferFilter {
+  pub fn matches(
+    &self,
+    offer_type: &str,
+    active: bool,
+    min_amount: u128,
+    max_amount: u128,
+    payments: &[OfferPayment],
+  ) -> bool {
+    if offer_type != self.offer_type || (self.active_only.unwrap_or(true) && !active) {
+      return false;
+    }
+    if let Some(amount) = self.amount {
+      if amount.0 < min_amount || amount.0 > max_amount {
+        return false;
+      }
+    }
+    match &self.payment_method {
+      None => true,
+      Some(method) => payments
+        .iter()
+        .any(|p| &p.method == method && self.amount.is_none_or(|a| p.accepts(a.0))),
+    }
+  }
+}
+
+// Sorts the ranked offers and returns the requested page
+pub fn sort_offer_page<T>(
+  mut entries: Vec<(OfferRank, T)>,
+  offer_type: &str,
+  sort: OfferSort,
+  from_index: Option<u64>,
+  limit: Option<u64>,
+) -> Vec<T> {
+  // Takers of a sell offer want the lowest rate, takers of a buy offer the highest
+  let lowest_first = offer_type == "sell";
+  entries.sort_by(|(a, _), (b, _)| match sort {
+    OfferSort::BestRate => match (a.rate, b.rate) {
+      (Some(x), Some(y)) if lowest_first => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+      (Some(x), Some(y)) => y.partial_cmp(&x).unwrap_or(Ordering::Equal),
+      (Some(_), None) => Ordering::Less,
+      (None, Some(_)) => Ordering::Greater,
+      (None, None) => Ordering::Equal,
+    },
+    OfferSort::Reputation => b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal),
+    OfferSort::Newest => b.created_on.cmp(&a.created_on),
+  });
+  entries
+    .into_iter()
+    .skip(from_index.unwrap_or(0) as usize)
+    .take(limit.unwrap_or(50) as usize)
+    .map(|(_, entry)| entry)
+    .collect()
+}
+
+#[near_bindgen]
+impl Contract {
+  pub(crate) fn index_offer(&mut self, asset: &str, currency: &str, offer_id: &str) {
+    let key = offer_book_key(asset, currency);
+    let mut ids = self.offer_book.get(&key).unwrap_or_default();
+    if !ids.iter().any(|id| id == offer_id) {
+      ids.push(offer_id.to_string());
+      self.offer_book.insert(&key, &ids);
+    }
+  }
+
+  pub(crate) fn unindex_offer(&mut self, asset: &str, currency: &str, offer_id: &str) {
+    let key = offer_book_key(asset, currency);
+    let mut ids = self.offer_book.get(&key).unwrap_or_default();
+    ids.retain(|id| id != offer_id);
+    self.offer_book.insert(&key, &ids);
+  }
+
+  // Drops the index buckets of Near offers (near_offers == true) or of token offers
+  pub(crate) fn clear_offer_book(&mut self, near_offers: bool) {
+    let prefix = offer_book_key(NEAR_ASSET, "");
+    let keys: Vec<String> = self
+      .offer_book
+      .keys()
+      .filter(|key| key.starts_with(&prefix) == near_offers)
+      .collect();
+    for key in keys {
+      self.offer_book.remove(&key);
+    }
+  }
+
+  pub(crate) fn offer_book_ids(&self, asset: &str, currency: &str) -> Vec<String> {
+    self
+      .offer_book
+      .get(&offer_book_key(asset, currency))
+      .unwrap_or_default()
+  }
+
+  pub fn get_offer_book(
+    &self,
+    filter: OfferFilter,
+    sort: Option<OfferSort>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+  ) -> Vec<CompleteOffer> {
+    let mut entries = Vec::new();
+    for offer_id in self.offer_book_ids(NEAR_ASSET, &filter.currency) {
+      let offer = match self.offers.get(&offer_id).map(Offer::from) {
+        Some(offer) => offer,
+        None => continue,
+      };
+      if !filter.matches(
+        &offer.offer_type,
+        offer.active,
+        offer.min_amount,
+        offer.max_amount,
+        &offer.payments,
+      ) {
+        continue;
+      }
+      let offerer = self.acc_pub_info(offer.offerer.clone());
+      let likes = offerer.as_ref().map_or(0, |info| info.likes);
+      if likes < filter.min_likes.unwrap_or(i32::MIN) {
+        continue;
+      }
+      let rank = OfferRank {
+        rate: self.resolve_offer_rate(&offer),
+        score: offerer.as_ref().and_then(|info| info.score).unwrap_or(0.0),
+        created_on: offer.created_on,
+      };
+      let mut comp_offer = offer.make_complete_offer(self.complete_offer_payments(&offer.payments));
+      comp_offer.offerer = offerer;
+      entries.push((rank, comp_offer));
+    }
+    sort_offer_page(
+      entries,
+      &filter.offer_type,
+      sort.unwrap_or(OfferSort::BestRate),
+      from_index,
+      limit,
+    )
+  }
+
+  pub fn get_token_offer_book(
+    &self,
+    token: AccountId,
+    filter: OfferFilter,
+    sort: Option<OfferSort>,
+    from_index: Option<u64>,
+    limit: Option<u64>,
+  ) -> Vec<CompleteTokenOffer> {
+    let mut entries = Vec::new();
+    for offer_id in self.offer_book_ids(token.as_str(), &filter.currency) {
+      let offer = match self.tokenoffers.get(&offer_id).map(TokenOffer::from) {
+        Some(offer) => offer,
+        None => continue,
+      };
+      if !filter.matches(
+        &offer.offer_type,
+        offer.active,
+        offer.min_amount,
+        offer.max_amount,
+        &offer.payments,
+      ) {
+        continue;
+      }
+      let offerer = self.acc_pub_info(offer.offerer.clone());
+      let likes = offerer.as_ref().map_or(0, |info| info.likes);
+      if likes < filter.min_likes.unwrap_or(i32::MIN) {
+        continue;
+      }
+      let rank = OfferRank {
+        rate: Some(offer.offer_rate),
+        score: offerer.as_ref().and_then(|info| info.score).unwrap_or(0.0),
+        created_on: offer.created_on,
+      };
+      let mut comp_offer = offer.make_complete_offer(self.complete_offer_payments(&offer.payments));
+      comp_offer.offerer = offerer;
+      comp_offer.token = self.get_token(offer.token.clone());
+      entries.push((rank, comp_offer));
+    }
+    sort_offer_page(
+      entries,
+      &filter.offer_type,
+      sort.unwrap_or(OfferSort::BestRate),
+      from_index,
+      limit,
+    )
+  }
+}
@@ -0,0 +1,168 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json::{self, json};
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Promise, PromiseResult, Timestamp,
+};
+
+use crate::constants::{GAS_FOR_BASIC_OP, GAS_FOR_GET_PRICE_DATA};
+use crate::*;
+
+// Price oracle settings used to resolve floating-rate offers
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OracleConfig {
+  pub oracle_id: AccountId,
+  pub asset_id: String,     // Asset id as known by the oracle, e.g. wrap.testnet
+  pub asset_decimals: u8,   // Decimals of the asset, 24 for Near
+  pub max_age_sec: u64,     // Prices older than this are considered stale
+}
+
+// Last reference price fetched from the oracle
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OraclePrice {
+  pub asset_id: String,
+  pub price: f64, // Price of one whole asset
+  pub timestamp: Timestamp,
+}
+
+// Shapes returned by the price oracle `get_price_data` view
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Price {
+  pub multiplier: U128,
+  pub decimals: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AssetOptionalPrice {
+  pub asset_id: String,
+  pub price: Option<Price>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PriceData {
+  pub timestamp: U64,
+  pub recency_duration_sec: u32,
+  pub prices: Vec<AssetOptionalPrice>,
+}
+
+impl OracleConfig {
+  pub fn new(oracle_id: AccountId, asset_id: String, asset_decimals: u8, max_age_sec: u64) -> Self {
+    Self {
+      oracle_id,
+      asset_id,
+      asset_decimals,
+      max_age_sec,
+    }
+  }
+}
+
+impl Default for OracleConfig {
+  fn default() -> Self {
+    Self::new(
+      "priceoracle.testnet".parse().unwrap(),
+      "wrap.testnet".to_string(),
+      24,
+      90,
+    )
+  }
+}
+
+impl OraclePrice {
+  pub fn is_fresh(&self, max_age_sec: u64) -> bool {
+    env::block_timestamp().saturating_sub(self.timestamp) <= max_age_sec * 1_000_000_000
+  }
+}
+
+impl Price {
+  // Converts the oracle multiplier into the price of one whole asset
+  pub fn to_f64(&self, asset_decimals: u8) -> f64 {
+    let exponent = i32::from(self.decimals) - i32::from(asset_decimals);
+    self.multiplier.0 as f64 / 10f64.powi(exponent)
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn set_oracle(&mut self, oracle_id: AccountId, asset_id: String, asset_decimals: u8, max_age_sec: u64) {
+    self.assert_role(Role::Admin);
+    self.oracle = OracleConfig::new(oracle_id, asset_id, asset_decimals, max_age_sec);
+    self.oracle_price = None;
+  }
+
+  pub fn get_oracle(&self) -> OracleConfig {
+    self.oracle.clone()
+  }
+
+  pub fn get_oracle_price(&self) -> Option<OraclePrice> {
+    self.oracle_price.clone()
+  }
+
+  // Anyone can refresh the reference price, the callback validates what the oracle returns
+  pub fn refresh_oracle_price(&mut self) -> Promise {
+    let cross_contract_call = Promise::new(self.oracle.oracle_id.clone()).function_call(
+      "get_price_data".to_string(),
+      json!({ "asset_ids": [self.oracle.asset_id.clone()] })
+        .to_string()
+        .into_bytes(),
+      0,
+      GAS_FOR_GET_PRICE_DATA,
+    );
+
+    let callback = Promise::new(env::current_account_id()).function_call(
+      "on_oracle_price".to_string(),
+      json!({}).to_string().into_bytes(),
+      0,
+      GAS_FOR_BASIC_OP,
+    );
+
+    cross_contract_call.then(callback)
+  }
+
+  #[private]
+  pub fn on_oracle_price(&mut self) -> Option<OraclePrice> {
+    let data: PriceData = match env::promise_result(0) {
+      PromiseResult::NotReady => unreachable!(),
+      PromiseResult::Successful(value) => {
+        serde_json::from_slice(&value).expect("Invalid price data returned by the oracle")
+      }
+      PromiseResult::Failed => panic!("Price oracle call failed"),
+    };
+
+    let price = data
+      .prices
+      .into_iter()
+      .find(|p| p.asset_id == self.oracle.asset_id)
+      .and_then(|p| p.price)
+      .expect("Oracle has no price for the asset");
+
+    let oracle_price = OraclePrice {
+      asset_id: self.oracle.asset_id.clone(),
+      price: price.to_f64(self.oracle.asset_decimals),
+      timestamp: data.timestamp.0,
+    };
+    assert!(
+      oracle_price.is_fresh(self.oracle.max_age_sec),
+      "Oracle price is stale"
+    );
+    self.oracle_price = Some(oracle_price.clone());
+    Some(oracle_price)
+  }
+
+  // Rate to freeze into a chat: the static offer rate, or the oracle price plus the offer margin
+  pub(crate) fn resolve_offer_rate(&self, offer: &Offer) -> Option<f32> {
+    match offer.margin {
+      None => Some(offer.offer_rate),
+      Some(margin) => self
+        .oracle_price
+        .as_ref()
+        .filter(|price| price.is_fresh(self.oracle.max_age_sec))
+        .map(|price| (price.price * (1.0 + f64::from(margin) / 100.0)) as f32),
+    }
+  }
+}
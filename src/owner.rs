@@ -1,5 +1,84 @@
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Timestamp,
+};
+
+use crate::ids::ADMIN_ACTION_ID_PREFIX;
 use crate::*;
 
+pub const DEFAULT_ADMIN_DELAY_SEC: u64 = 24 * 60 * 60;
+
+// Admin changes users should see coming, applied only after the admin delay
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum AdminAction {
+  UpdateSendCost { cost: f32 },
+  SetFeeSchedule { schedule: Vec<FeeTier> },
+  RemoveWhitelistedToken { token: AccountId },
+  ProposeNewOwner { proposed_owner_id: AccountId },
+  GrantRole { role: Role, account_id: AccountId },
+  SetAdminDelay { delay_sec: u64 },
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingAdminAction {
+  pub id: String,
+  pub action: AdminAction,
+  pub proposer: AccountId,
+  pub proposed_at: Timestamp,
+  pub executable_at: Timestamp,
+}
+
+impl AdminAction {
+  // Role allowed to propose and execute the action, None for owner only actions
+  pub fn required_role(&self) -> Option<Role> {
+    match self {
+      AdminAction::UpdateSendCost { .. } | AdminAction::SetFeeSchedule { .. } => Some(Role::FeeManager),
+      AdminAction::RemoveWhitelistedToken { .. } => Some(Role::TokenManager),
+      AdminAction::ProposeNewOwner { .. } | AdminAction::GrantRole { .. } | AdminAction::SetAdminDelay { .. } => None,
+    }
+  }
+}
+
+impl Contract {
+  fn assert_can_run_admin_action(&self, action: &AdminAction) {
+    match action.required_role() {
+      Some(role) => self.assert_role(role),
+      None => self.assert_owner(),
+    }
+  }
+
+  // Queues the action and returns its id
+  pub(crate) fn queue_admin_action(&mut self, action: AdminAction) -> String {
+    self.assert_can_run_admin_action(&action);
+    let id = self.next_id(ADMIN_ACTION_ID_PREFIX);
+    let pending = PendingAdminAction {
+      id: id.clone(),
+      action,
+      proposer: env::predecessor_account_id(),
+      proposed_at: env::block_timestamp(),
+      executable_at: env::block_timestamp() + self.admin_delay_sec * 1_000_000_000,
+    };
+    self.pending_admin_actions.insert(&id, &pending);
+    id
+  }
+
+  fn apply_admin_action(&mut self, action: AdminAction) {
+    match action {
+      AdminAction::UpdateSendCost { cost } => self.send_cost = cost,
+      AdminAction::SetFeeSchedule { schedule } => self.fee_schedule = schedule,
+      AdminAction::RemoveWhitelistedToken { token } => {
+        self.whitelistedtokens.remove(&token);
+      }
+      AdminAction::ProposeNewOwner { proposed_owner_id } => self.proposed_owner_id = proposed_owner_id,
+      AdminAction::GrantRole { role, account_id } => self.add_role_member(role, account_id),
+      AdminAction::SetAdminDelay { delay_sec } => self.admin_delay_sec = delay_sec,
+    }
+  }
+}
+
 #[near_bindgen]
 impl Contract {
   pub(crate) fn assert_owner(&self) {
@@ -8,16 +87,16 @@ impl Contract {
     }
   }
 
-  // pub(crate) fn assert_owner_or_guardian(&self) {
-  //   let predecessor_id = env::predecessor_account_id();
-  //   if predecessor_id != self.owner_id && !self.guardians.contains(&predecessor_id) {
-  //     env::panic_str("This method can be called only by owner or guardian")
-  //   }
-  // }
+  pub(crate) fn assert_owner_or_guardian(&self) {
+    let predecessor_id = env::predecessor_account_id();
+    if predecessor_id != self.owner_id && !self.guardians.contains(&predecessor_id) {
+      panic!("This method can be called only by owner or guardian")
+    }
+  }
 
-  pub fn propose_new_owner(&mut self, proposed_owner_id: AccountId) {
-    self.assert_owner();
-    self.proposed_owner_id = proposed_owner_id;
+  /// Queues the ownership proposal, accept_ownership works once it is executed.
+  pub fn propose_new_owner(&mut self, proposed_owner_id: AccountId) -> String {
+    self.queue_admin_action(AdminAction::ProposeNewOwner { proposed_owner_id })
   }
 
   pub fn accept_ownership(&mut self) {
@@ -53,4 +132,35 @@ impl Contract {
   pub fn guardians(&self) -> Vec<AccountId> {
     self.guardians.to_vec()
   }
+
+  pub fn set_admin_delay(&mut self, delay_sec: u64) -> String {
+    self.queue_admin_action(AdminAction::SetAdminDelay { delay_sec })
+  }
+
+  pub fn get_admin_delay(&self) -> u64 {
+    self.admin_delay_sec
+  }
+
+  /// Applies a queued action once its delay has passed. The caller needs the
+  /// same permission the action needed when it was proposed.
+  pub fn execute_admin_action(&mut self, id: String) {
+    let pending = self.pending_admin_actions.get(&id).expect("Admin action not found");
+    self.assert_can_run_admin_action(&pending.action);
+    assert!(
+      env::block_timestamp() >= pending.executable_at,
+      "Admin action is still in its delay"
+    );
+    self.pending_admin_actions.remove(&id);
+    self.apply_admin_action(pending.action);
+  }
+
+  /// Cancels a queued action. Can be called by owner or guardians.
+  pub fn cancel_admin_action(&mut self, id: String) {
+    self.assert_owner_or_guardian();
+    self.pending_admin_actions.remove(&id).expect("Admin action not found");
+  }
+
+  pub fn get_pending_admin_actions(&self) -> Vec<PendingAdminAction> {
+    self.pending_admin_actions.values().collect()
+  }
 }
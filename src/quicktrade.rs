@@ -0,0 +1,197 @@
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::offerbook::{sort_offer_page, OfferFilter, OfferRank, OfferSort, NEAR_ASSET};
+use crate::*;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QuickTradeResult {
+  pub chat_id: String,
+  pub offer_id: String,
+  pub offerer: AccountId,
+  pub rate: f32,
+}
+
+// Offer type a taker trades against: buying the asset takes sell offers and vice versa
+pub fn counter_offer_type(side: &str) -> String {
+  match side {
+    "buy" => "sell".to_string(),
+    "sell" => "buy".to_string(),
+    _ => panic!("Side must be either buy or sell"),
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  // Near offers the taker can open a chat on right now, best rate first
+  fn quick_trade_candidates(&self, taker: &AccountId, filter: &OfferFilter) -> Vec<String> {
+    let amount = filter.amount.unwrap().0;
+    let mut entries = Vec::new();
+    for offer_id in self.offer_book_ids(NEAR_ASSET, &filter.currency) {
+      let offer = match self.offers.get(&offer_id).map(Offer::from) {
+        Some(offer) => offer,
+        None => continue,
+      };
+      if &offer.offerer == taker
+        || self.is_blocked_between(taker.clone(), offer.offerer.clone())
+        || self.check_offer_requirements(&offer.requirements, taker.clone()).is_some()
+        || !filter.matches(&offer.offer_type, offer.active, offer.min_amount, offer.max_amount, &offer.payments)
+      {
+        continue;
+      }
+      // Sell offers lock the offerer's balance, buy offers lock the taker's
+      let escrow = if offer.offer_type == "sell" { &offer.offerer } else { taker };
+      let trade_cost = self.trade_fee(escrow, NEAR_ASSET).0;
+      if self.pub_get_account(escrow.clone()).map_or(0, |acc| acc.balance) < amount + trade_cost {
+        continue;
+      }
+      let rate = match self.resolve_offer_rate(&offer) {
+        Some(rate) => rate,
+        None => continue,
+      };
+      let rank = OfferRank {
+        rate: Some(rate),
+        score: 0.0,
+        created_on: offer.created_on,
+      };
+      entries.push((rank, offer_id));
+    }
+    sort_offer_page(entries, &filter.offer_type, OfferSort::BestRate, None, None)
+  }
+
+  fn quick_trade_token_candidates(
+    &self,
+    taker: &AccountId,
+    token: &AccountId,
+    filter: &OfferFilter,
+  ) -> Vec<String> {
+    let amount = filter.amount.unwrap().0;
+    let mut entries = Vec::new();
+    for offer_id in self.offer_book_ids(token.as_str(), &filter.currency) {
+      let offer = match self.tokenoffers.get(&offer_id).map(TokenOffer::from) {
+        Some(offer) => offer,
+        None => continue,
+      };
+      if &offer.offerer == taker
+        || self.is_blocked_between(taker.clone(), offer.offerer.clone())
+        || self.check_offer_requirements(&offer.requirements, taker.clone()).is_some()
+        || !filter.matches(&offer.offer_type, offer.active, offer.min_amount, offer.max_amount, &offer.payments)
+      {
+        continue;
+      }
+      let escrow = if offer.offer_type == "sell" { &offer.offerer } else { taker };
+      let trade_cost = self.trade_fee(escrow, token.as_str()).0;
+      let liquid = self.pub_get_account(escrow.clone()).is_some_and(|acc| {
+        acc.tokens.get(token).unwrap_or(0) >= amount && acc.balance >= trade_cost
+      });
+      if !liquid {
+        continue;
+      }
+      let rank = OfferRank {
+        rate: Some(offer.offer_rate),
+        score: 0.0,
+        created_on: offer.created_on,
+      };
+      entries.push((rank, offer_id));
+    }
+    sort_offer_page(entries, &filter.offer_type, OfferSort::BestRate, None, None)
+  }
+
+  /// Opens a chat on the best priced offer that can take the trade. `asset` is
+  /// "near" or a token account id, `side` is what the caller wants to do with it.
+  pub fn quick_trade(
+    &mut self,
+    asset: String,
+    side: String,
+    amount: U128,
+    currency: String,
+    payment_method: String,
+  ) -> QuickTradeResult {
+    self.assert_not_paused(Subsystem::Swaps);
+    let taker = env::predecessor_account_id();
+    let filter = OfferFilter {
+      offer_type: counter_offer_type(&side),
+      currency,
+      payment_method: Some(payment_method.clone()),
+      amount: Some(amount),
+      min_likes: None,
+      active_only: Some(true),
+    };
+    let (offer_id, offerer, rate, result) = if asset == NEAR_ASSET {
+      let offer_id = self
+        .quick_trade_candidates(&taker, &filter)
+        .into_iter()
+        .next()
+        .expect("No offer available for this trade");
+      let offer = self.get_offer(offer_id.clone()).unwrap();
+      let rate = self.resolve_offer_rate(&offer).unwrap();
+      let result = if offer.offer_type == "sell" {
+        self.open_sell_chat(
+          None,
+          offer_id.clone(),
+          taker.clone(),
+          amount,
+          taker.clone(),
+          offer.offerer.clone(),
+          payment_method,
+          "".to_string(),
+        )
+      } else {
+        self.open_buy_chat(
+          None,
+          offer_id.clone(),
+          taker.clone(),
+          amount,
+          taker.clone(),
+          offer.offerer.clone(),
+          payment_method,
+          "".to_string(),
+        )
+      };
+      (offer_id, offer.offerer, rate, result)
+    } else {
+      let token: AccountId = asset.parse().unwrap();
+      let offer_id = self
+        .quick_trade_token_candidates(&taker, &token, &filter)
+        .into_iter()
+        .next()
+        .expect("No offer available for this trade");
+      let offer = self.get_token_offer(offer_id.clone()).unwrap();
+      let result = if offer.offer_type == "sell" {
+        self.open_token_sell_chat(
+          None,
+          offer_id.clone(),
+          token,
+          taker.clone(),
+          amount,
+          taker.clone(),
+          offer.offerer.clone(),
+          payment_method,
+          "".to_string(),
+        )
+      } else {
+        self.open_token_buy_chat(
+          None,
+          offer_id.clone(),
+          token,
+          taker.clone(),
+          amount,
+          taker.clone(),
+          offer.offerer.clone(),
+          payment_method,
+          "".to_string(),
+        )
+      };
+      (offer_id, offer.offerer, offer.offer_rate, result)
+    };
+
+    let chat_id = result.unwrap_or_else(|err| env::panic_str(&err));
+    QuickTradeResult {
+      chat_id,
+      offer_id,
+      offerer,
+      rate,
+    }
+  }
+}
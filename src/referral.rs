@@ -0,0 +1,108 @@
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp,
+};
+
+use crate::*;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralConfig {
+  pub share_bps: u32,  // Share of each trade fee paid to the referrer, in basis points
+  pub window_sec: u64, // How long after registration the referee's fees are shared
+}
+
+impl Default for ReferralConfig {
+  fn default() -> Self {
+    Self {
+      share_bps: 2_000,
+      window_sec: 180 * 24 * 60 * 60,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Referral {
+  pub referrer: AccountId,
+  pub registered_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralStats {
+  pub referred: u32,
+  pub earned: Balance, // Near credited from referees' trade fees
+}
+
+impl Contract {
+  // Returns why the referrer can't be used, if it can't
+  pub(crate) fn check_referrer(&self, account_id: &AccountId, referrer: &AccountId) -> Option<String> {
+    if referrer == account_id {
+      return Some("You can't refer yourself".to_string());
+    }
+    if self.pub_get_account(referrer.clone()).is_none() {
+      return Some("Referrer is not registered".to_string());
+    }
+    None
+  }
+
+  pub(crate) fn add_referral(&mut self, account_id: &AccountId, referrer: AccountId) {
+    let mut stats = self.referral_stats.get(&referrer).unwrap_or_default();
+    stats.referred += 1;
+    self.referral_stats.insert(&referrer, &stats);
+    self.referrals.insert(
+      account_id,
+      &Referral {
+        referrer,
+        registered_at: env::block_timestamp(),
+      },
+    );
+  }
+
+  /// Credits the referrer's share of a trade fee paid by `account_id` and
+  /// returns it, zero when there is no referrer or the window has passed.
+  pub(crate) fn pay_referral_share(&mut self, account_id: &AccountId, fee: Balance) -> Balance {
+    let referral = match self.referrals.get(account_id) {
+      Some(referral) => referral,
+      None => return 0,
+    };
+    let window_end = referral.registered_at + self.referral_config.window_sec * 1_000_000_000;
+    if env::block_timestamp() > window_end {
+      return 0;
+    }
+    let share = fee * Balance::from(self.referral_config.share_bps) / 10_000;
+    let mut referrer = match self.get_account(referral.referrer.clone()) {
+      Some(referrer) if share > 0 => referrer,
+      _ => return 0,
+    };
+    referrer.balance += share;
+    drop(referrer);
+    let mut stats = self.referral_stats.get(&referral.referrer).unwrap_or_default();
+    stats.earned += share;
+    self.referral_stats.insert(&referral.referrer, &stats);
+    share
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn set_referral_config(&mut self, config: ReferralConfig) {
+    self.assert_role(Role::FeeManager);
+    assert!(config.share_bps <= 10_000, "Referral share can't be above 100%");
+    self.referral_config = config;
+  }
+
+  pub fn get_referral_config(&self) -> ReferralConfig {
+    self.referral_config.clone()
+  }
+
+  pub fn get_referrer(&self, account_id: AccountId) -> Option<AccountId> {
+    self.referrals.get(&account_id).map(|referral| referral.referrer)
+  }
+
+  pub fn get_referral_stats(&self, account_id: AccountId) -> ReferralStats {
+    self.referral_stats.get(&account_id).unwrap_or_default()
+  }
+}
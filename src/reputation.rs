@@ -0,0 +1,238 @@
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp,
+};
+
+use crate::*;
+
+pub const MAX_RATING_COMMENT_LEN: usize = 280;
+
+// Trade history of an account, updated as its chats open, release and cancel
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Reputation {
+  pub positive_volume: f64, // Volume of the trades rated positively, in whole asset units
+  pub negative_volume: f64,
+  pub opened: u32,
+  pub completed: u32,
+  pub canceled: u32,
+  pub releases: u32, // Trades this account released escrow on
+  pub release_time_total: u64,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Rating {
+  pub chat_id: String,
+  pub asset: String, // near or the token account id
+  pub from: AccountId,
+  pub to: AccountId,
+  pub positive: bool,
+  pub comment: Option<String>,
+  pub amount: Balance,
+  pub created_on: Timestamp,
+}
+
+impl Rating {
+  /// Rating by the caller on the other participant of a chat, panics for non-participants
+  pub fn new(
+    chat_id: String,
+    asset: String,
+    payer: &AccountId,
+    receiver: &AccountId,
+    amount: Balance,
+    positive: bool,
+    comment: Option<String>,
+  ) -> Self {
+    let from = env::predecessor_account_id();
+    let to = if &from == payer {
+      receiver.clone()
+    } else if &from == receiver {
+      payer.clone()
+    } else {
+      panic!("Only chat participants can rate the chat")
+    };
+    if let Some(comment) = &comment {
+      assert!(
+        comment.chars().count() <= MAX_RATING_COMMENT_LEN,
+        "Rating comment is longer than {} characters",
+        MAX_RATING_COMMENT_LEN
+      );
+    }
+    Self {
+      chat_id,
+      asset,
+      from,
+      to,
+      positive,
+      comment,
+      amount,
+      created_on: env::block_timestamp(),
+    }
+  }
+}
+
+impl Reputation {
+  // Percentage of the rated volume that was rated positively
+  pub fn score(&self) -> Option<f32> {
+    let total = self.positive_volume + self.negative_volume;
+    if total > 0.0 {
+      Some((self.positive_volume / total * 100.0) as f32)
+    } else {
+      None
+    }
+  }
+
+  pub fn completion_rate(&self) -> Option<f32> {
+    Self::rate(self.completed, self.opened)
+  }
+
+  pub fn cancellation_rate(&self) -> Option<f32> {
+    Self::rate(self.canceled, self.opened)
+  }
+
+  pub fn average_release_time(&self) -> Option<u64> {
+    if self.releases > 0 {
+      Some(self.release_time_total / u64::from(self.releases))
+    } else {
+      None
+    }
+  }
+
+  fn rate(count: u32, total: u32) -> Option<f32> {
+    if total > 0 {
+      Some(count as f32 / total as f32 * 100.0)
+    } else {
+      None
+    }
+  }
+}
+
+impl Contract {
+  pub(crate) fn record_chat_opened(&mut self, payer: &AccountId, receiver: &AccountId) {
+    for account_id in [payer, receiver] {
+      if let Some(mut acc) = self.get_account(account_id.clone()) {
+        acc.reputation.opened += 1;
+      }
+    }
+  }
+
+  pub(crate) fn record_chat_released(
+    &mut self,
+    payer: &AccountId,
+    receiver: &AccountId,
+    releaser: &AccountId,
+    started_at: Timestamp,
+  ) {
+    for account_id in [payer, receiver] {
+      if let Some(mut acc) = self.get_account(account_id.clone()) {
+        acc.reputation.completed += 1;
+      }
+    }
+    if let Some(mut acc) = self.get_account(releaser.clone()) {
+      acc.reputation.releases += 1;
+      acc.reputation.release_time_total += env::block_timestamp().saturating_sub(started_at);
+    }
+  }
+
+  pub(crate) fn record_chat_canceled(&mut self, payer: &AccountId, receiver: &AccountId) {
+    for account_id in [payer, receiver] {
+      if let Some(mut acc) = self.get_account(account_id.clone()) {
+        acc.reputation.canceled += 1;
+      }
+    }
+  }
+
+  // Decimals of near or of a listed token, unknown tokens are treated like near
+  pub(crate) fn asset_decimals(&self, asset: &str) -> u8 {
+    if asset == offerbook::NEAR_ASSET {
+      24
+    } else {
+      asset
+        .parse()
+        .ok()
+        .and_then(|token: AccountId| self.get_token(token))
+        .map_or(24, |meta| meta.decimals)
+    }
+  }
+
+  // Amount in whole units of the asset, used to weight ratings by traded volume
+  fn rating_volume(&self, asset: &str, amount: Balance) -> f64 {
+    amount as f64 / 10f64.powi(i32::from(self.asset_decimals(asset)))
+  }
+
+  // Credits a rating to the rated account and stores it
+  pub(crate) fn add_rating(&mut self, rating: Rating) {
+    let volume = self.rating_volume(&rating.asset, rating.amount);
+    let mut acc = self.get_account(rating.to.clone()).expect("Account not found");
+    if rating.positive {
+      acc.add_like();
+      acc.reputation.positive_volume += volume;
+    } else {
+      acc.add_dislike();
+      acc.reputation.negative_volume += volume;
+    }
+    drop(acc);
+    self.ratings.push(&rating);
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn rate_chat(&mut self, chat_id: String, positive: bool, comment: Option<String>) {
+    self.assert_not_emergency();
+    let mut chat = self.chats.get(&chat_id).map(Chat::from).expect("Chat not found");
+    assert!(chat.released, "Only released chats can be rated");
+    let rating = Rating::new(
+      chat_id.clone(),
+      offerbook::NEAR_ASSET.to_string(),
+      &chat.payer,
+      &chat.receiver,
+      chat.amount,
+      positive,
+      comment,
+    );
+    if rating.from == chat.payer {
+      assert!(!chat.payer_has_rated, "You have already rated this chat");
+      chat.payer_has_rated = true;
+    } else {
+      assert!(!chat.receiver_has_rated, "You have already rated this chat");
+      chat.receiver_has_rated = true;
+    }
+    self.add_rating(rating);
+    self.chats.insert(&chat_id, &VersionedChat::from(chat));
+  }
+
+  pub fn rate_token_chat(&mut self, chat_id: String, positive: bool, comment: Option<String>) {
+    self.assert_not_emergency();
+    let mut chat = self.tokenchats.get(&chat_id).map(TokenChat::from).expect("Chat not found");
+    assert!(chat.released, "Only released chats can be rated");
+    let rating = Rating::new(
+      chat_id.clone(),
+      chat.token_id.to_string(),
+      &chat.payer,
+      &chat.receiver,
+      chat.amount,
+      positive,
+      comment,
+    );
+    if rating.from == chat.payer {
+      assert!(!chat.payer_has_rated, "You have already rated this chat");
+      chat.payer_has_rated = true;
+    } else {
+      assert!(!chat.receiver_has_rated, "You have already rated this chat");
+      chat.receiver_has_rated = true;
+    }
+    self.add_rating(rating);
+    self.tokenchats.insert(&chat_id, &VersionedTokenChat::from(chat));
+  }
+
+  pub fn get_account_ratings(&self, account_id: AccountId) -> Vec<Rating> {
+    self
+      .ratings
+      .iter()
+      .filter(|rating| rating.to == account_id)
+      .collect()
+  }
+}
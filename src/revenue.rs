@@ -18,17 +18,19 @@ pub struct Revenue {
 
 impl Revenue {}
 
-#[near_bindgen]
 impl Contract {
-  pub fn create_revenue(&mut self, asset: String, from: String, account: AccountId, amount: u128, amount_usd: f64 ) {
+  // Takes the fee out of the account's locked balance into the contract revenue,
+  // only the account itself can settle its fee
+  pub(crate) fn record_revenue(&mut self, asset: String, from: String, account: AccountId, amount: u128, amount_usd: f64) {
+    let mut acc = self.get_account(account.clone()).unwrap();
+    acc.unlock(amount);
+    acc.withdraw(amount);
+    drop(acc);
 
-    self
-    .get_account(account.clone())
-    .unwrap().unlock(amount.clone());
-
-    self
-    .get_account(account.clone())
-    .unwrap().withdraw(amount.clone());
+    // Trade fees are shared with the referrer of the account paying them
+    let share = if from == "trade" { self.pay_referral_share(&account, amount) } else { 0 };
+    let amount_usd = amount_usd * (amount - share) as f64 / amount.max(1) as f64;
+    let amount = amount - share;
 
     let rev = Revenue {
       asset,
@@ -0,0 +1,95 @@
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId,
+};
+
+use crate::owner::AdminAction;
+use crate::*;
+
+// Roles the owner can grant to run parts of the contract without the owner key
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+  Admin,        // Payment methods, oracle, bond config and every other role's methods
+  TokenManager, // Listed and whitelisted tokens
+  FeeManager,   // Send cost and revenue
+  Arbiter,      // Escrow unlocks and bond slashing on disputes
+  Pauser,       // Pausing subsystems and starting emergency mode
+}
+
+impl Contract {
+  // The owner and admins pass every role check
+  pub(crate) fn has_role_or_admin(&self, role: Role, account_id: &AccountId) -> bool {
+    *account_id == self.owner_id || self.has_role(Role::Admin, account_id.clone()) || self.has_role(role, account_id.clone())
+  }
+
+  pub(crate) fn assert_role(&self, role: Role) {
+    if !self.has_role_or_admin(role, &env::predecessor_account_id()) {
+      panic!("This method requires the {:?} role", role)
+    }
+  }
+
+  // Guardians keep the emergency powers they had before roles existed
+  pub(crate) fn assert_role_or_guardian(&self, role: Role) {
+    let predecessor_id = env::predecessor_account_id();
+    if !self.has_role_or_admin(role, &predecessor_id) && !self.guardians.contains(&predecessor_id) {
+      panic!("This method requires the {:?} role", role)
+    }
+  }
+
+  pub(crate) fn add_role_member(&mut self, role: Role, account_id: AccountId) {
+    let mut members = self.roles.get(&role).unwrap_or_default();
+    if members.contains(&account_id) {
+      env::panic_str(&format!("{} already has the {:?} role", account_id, role));
+    }
+    members.push(account_id);
+    self.roles.insert(&role, &members);
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Queues a role grant, see execute_admin_action. Only can be called by owner.
+  pub fn grant_role(&mut self, role: Role, account_id: AccountId) -> String {
+    if self.has_role(role, account_id.clone()) {
+      env::panic_str(&format!("{} already has the {:?} role", account_id, role));
+    }
+    self.queue_admin_action(AdminAction::GrantRole { role, account_id })
+  }
+
+  /// Revokes a role. Only can be called by owner.
+  pub fn revoke_role(&mut self, role: Role, account_id: AccountId) {
+    self.assert_owner();
+    let mut members = self.roles.get(&role).unwrap_or_default();
+    if !members.contains(&account_id) {
+      env::panic_str(&format!("{} doesn't have the {:?} role", account_id, role));
+    }
+    members.retain(|member| *member != account_id);
+    self.roles.insert(&role, &members);
+  }
+
+  pub fn renounce_role(&mut self, role: Role) {
+    let account_id = env::predecessor_account_id();
+    let mut members = self.roles.get(&role).unwrap_or_default();
+    members.retain(|member| *member != account_id);
+    self.roles.insert(&role, &members);
+  }
+
+  pub fn has_role(&self, role: Role, account_id: AccountId) -> bool {
+    self.roles.get(&role).is_some_and(|members| members.contains(&account_id))
+  }
+
+  pub fn get_role_members(&self, role: Role) -> Vec<AccountId> {
+    self.roles.get(&role).unwrap_or_default()
+  }
+
+  pub fn get_account_roles(&self, account_id: AccountId) -> Vec<Role> {
+    self
+      .roles
+      .iter()
+      .filter(|(_role, members)| members.contains(&account_id))
+      .map(|(role, _members)| role)
+      .collect()
+  }
+}
@@ -0,0 +1,243 @@
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Timestamp, ONE_NEAR,
+};
+
+use crate::ids::SCHEDULE_ID_PREFIX;
+use crate::offerbook::NEAR_ASSET;
+use crate::*;
+
+// Schedules executed by one execute_due_schedules call when no limit is given
+pub const DEFAULT_CRANK_LIMIT: u32 = 20;
+pub const MIN_SCHEDULE_INTERVAL_SEC: u64 = 3600;
+// Attached on create to pay for the schedule's storage, credited back to the
+// payer's balance once the schedule ends or is cancelled
+pub const SCHEDULE_STORAGE_DEPOSIT: Balance = ONE_NEAR / 100;
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ScheduleStatus {
+  Active,
+  Ended,
+  Cancelled,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Schedule {
+  pub id: String,
+  pub payer: AccountId,
+  pub recipient: AccountId,
+  pub asset: String, // near or the token account id
+  pub amount: Balance,
+  pub interval_sec: u64,
+  pub next_run_at: Timestamp,
+  pub ends_at: Option<Timestamp>, // Runs until cancelled when unset
+  pub status: ScheduleStatus,
+  pub executions: u32,
+  pub missed: u32, // Runs skipped for lack of balance or not cranked in time
+  pub last_transfer_id: Option<String>,
+  pub storage_deposit: Balance,
+  pub created_on: Timestamp,
+}
+
+impl Contract {
+  // Pays one due run of the schedule, a run the payer can't cover is skipped.
+  // Runs whose time passed before the crank got to them are counted as missed
+  // so the next run is always in the future.
+  fn run_schedule(&mut self, schedule: &mut Schedule) {
+    let payable = self.pub_get_account(schedule.recipient.clone()).is_some()
+      && self.check_denylisted(&[&schedule.payer, &schedule.recipient]).is_none()
+      && self.free_balance(&schedule.payer, &schedule.asset) >= schedule.amount;
+    if payable {
+      let transfer_id = self.move_balance(&schedule.payer, &schedule.recipient, &schedule.asset, schedule.amount);
+      schedule.executions += 1;
+      schedule.last_transfer_id = Some(transfer_id);
+    } else {
+      schedule.missed += 1;
+    }
+    let now = env::block_timestamp();
+    let interval = schedule.interval_sec * 1_000_000_000;
+    schedule.next_run_at += interval;
+    if schedule.next_run_at <= now {
+      let skipped = (now - schedule.next_run_at) / interval + 1;
+      let last_run = schedule.ends_at.map_or(now, |ends_at| ends_at.min(now));
+      if schedule.next_run_at <= last_run {
+        schedule.missed += ((last_run - schedule.next_run_at) / interval + 1) as u32;
+      }
+      schedule.next_run_at += skipped * interval;
+    }
+    if schedule.ends_at.is_some_and(|ends_at| schedule.next_run_at > ends_at) {
+      schedule.status = ScheduleStatus::Ended;
+    }
+  }
+
+  fn queue_schedule(&mut self, schedule: &Schedule) {
+    let mut ids = self.schedule_queue.get(&schedule.next_run_at).unwrap_or_default();
+    ids.push(schedule.id.clone());
+    self.schedule_queue.insert(&schedule.next_run_at, &ids);
+  }
+
+  fn unqueue_schedule(&mut self, schedule: &Schedule) {
+    let mut ids = self.schedule_queue.get(&schedule.next_run_at).unwrap_or_default();
+    ids.retain(|id| id != &schedule.id);
+    if ids.is_empty() {
+      self.schedule_queue.remove(&schedule.next_run_at);
+    } else {
+      self.schedule_queue.insert(&schedule.next_run_at, &ids);
+    }
+  }
+
+  // Ended and cancelled schedules are dropped, the event keeps their outcome
+  fn close_schedule(&mut self, schedule: Schedule) {
+    self.schedules.remove(&schedule.id);
+    if let Some(mut acc) = self.get_account(schedule.payer.clone()) {
+      acc.balance += schedule.storage_deposit;
+    }
+    emit_event(
+      "schedule_closed",
+      json!({
+        "schedule_id": schedule.id,
+        "status": schedule.status,
+        "executions": schedule.executions,
+        "missed": schedule.missed,
+      }),
+    );
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Authorizes `recipient` to be paid `amount` from the caller's balance
+  /// every `interval_sec`, starting `start_in_sec` from now. Attach
+  /// SCHEDULE_STORAGE_DEPOSIT to cover the schedule's storage.
+  #[payable]
+  pub fn create_schedule(
+    &mut self,
+    recipient: AccountId,
+    asset: String,
+    amount: U128,
+    interval_sec: u64,
+    start_in_sec: u64,
+    end_in_sec: Option<u64>,
+  ) -> String {
+    let payer = env::predecessor_account_id();
+    assert!(
+      env::attached_deposit() >= SCHEDULE_STORAGE_DEPOSIT,
+      "{}",
+      ERR2_INSUFFICIENT_STORAGE
+    );
+    assert!(
+      self.pub_get_account(payer.clone()).is_some(),
+      "You must be a registered user to schedule transfers"
+    );
+    assert!(
+      self.pub_get_account(recipient.clone()).is_some(),
+      "Recipient {} is not registered",
+      recipient
+    );
+    assert!(recipient != payer, "You can't schedule transfers to yourself");
+    if let Some(err) = self.check_denylisted(&[&payer, &recipient]) {
+      panic!("{}", err);
+    }
+    assert!(
+      asset == NEAR_ASSET || asset.parse().ok().and_then(|token| self.get_token(token)).is_some(),
+      "Token {} is not supported",
+      asset
+    );
+    assert!(amount.0 > 0, "Amount must be greater than zero");
+    assert!(
+      interval_sec >= MIN_SCHEDULE_INTERVAL_SEC,
+      "Interval must be at least {} seconds",
+      MIN_SCHEDULE_INTERVAL_SEC
+    );
+    let now = env::block_timestamp();
+    let id = self.next_id(SCHEDULE_ID_PREFIX);
+    let schedule = Schedule {
+      id: id.clone(),
+      payer,
+      recipient,
+      asset,
+      amount: amount.0,
+      interval_sec,
+      next_run_at: now + start_in_sec * 1_000_000_000,
+      ends_at: end_in_sec.map(|sec| now + sec * 1_000_000_000),
+      status: ScheduleStatus::Active,
+      executions: 0,
+      missed: 0,
+      last_transfer_id: None,
+      storage_deposit: env::attached_deposit(),
+      created_on: now,
+    };
+    self.schedules.insert(&id, &schedule);
+    self.queue_schedule(&schedule);
+    id
+  }
+
+  /// Permissionless crank, pays up to `limit` schedules in order of their due
+  /// time and returns how many were processed. Only due schedules are read,
+  /// and a schedule that fell behind is paid once with the runs it missed
+  /// counted in `missed`.
+  pub fn execute_due_schedules(&mut self, limit: Option<u32>) -> u32 {
+    self.assert_not_emergency();
+    let now = env::block_timestamp();
+    let limit = limit.unwrap_or(DEFAULT_CRANK_LIMIT);
+    let mut processed = 0;
+    while processed < limit {
+      let run_at = match self.schedule_queue.min() {
+        Some(run_at) if run_at <= now => run_at,
+        _ => break,
+      };
+      let mut ids = self.schedule_queue.get(&run_at).unwrap();
+      let id = ids.remove(0);
+      if ids.is_empty() {
+        self.schedule_queue.remove(&run_at);
+      } else {
+        self.schedule_queue.insert(&run_at, &ids);
+      }
+      let mut schedule = match self.schedules.get(&id) {
+        Some(schedule) => schedule,
+        None => continue,
+      };
+      self.run_schedule(&mut schedule);
+      if schedule.status == ScheduleStatus::Active {
+        self.schedules.insert(&id, &schedule);
+        self.queue_schedule(&schedule);
+      } else {
+        self.close_schedule(schedule);
+      }
+      processed += 1;
+    }
+    processed
+  }
+
+  pub fn cancel_schedule(&mut self, schedule_id: String) -> String {
+    let mut schedule = match self.schedules.get(&schedule_id) {
+      Some(schedule) => schedule,
+      None => return "Schedule not found".to_string(),
+    };
+    if schedule.payer != env::predecessor_account_id() {
+      return "Only the payer can cancel the schedule".to_string();
+    }
+    self.unqueue_schedule(&schedule);
+    schedule.status = ScheduleStatus::Cancelled;
+    self.close_schedule(schedule);
+    "Schedule cancelled".to_string()
+  }
+
+  pub fn get_schedule(&self, schedule_id: String) -> Option<Schedule> {
+    self.schedules.get(&schedule_id)
+  }
+
+  // Schedules paid by or to the account
+  pub fn get_account_schedules(&self, account_id: AccountId) -> Vec<Schedule> {
+    self
+      .schedules
+      .values()
+      .filter(|schedule| schedule.payer == account_id || schedule.recipient == account_id)
+      .collect()
+  }
+}
@@ -3,11 +3,17 @@
 pub mod tests {
   // use super::*;
   use crate::constants::*;
+  use crate::offer::OfferRequirements;
+  use crate::offerbook::{OfferFilter, OfferSort, NEAR_ASSET};
+  use crate::roles::Role;
+  use crate::schedule::{MIN_SCHEDULE_INTERVAL_SEC, SCHEDULE_STORAGE_DEPOSIT};
+  use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
   use crate::*;
   use near_sdk::json_types::U128;
+  use near_sdk::serde_json::json;
   use near_sdk::test_utils::test_env::alice;
   use near_sdk::test_utils::VMContextBuilder;
-  use near_sdk::{testing_env, VMContext, ONE_NEAR};
+  use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig, VMConfig, VMContext, ONE_NEAR};
 
   pub fn get_context(is_view: bool) -> VMContext {
     VMContextBuilder::new()
@@ -20,6 +26,25 @@ pub mod tests {
       .build()
   }
 
+  pub fn add_payment_methods(contract: &mut Contract) {
+    for name in ["M-Pesa", "Bank Transfer"] {
+      let method = PaymentMethod {
+        name: name.to_string(),
+        icon: "".to_string(),
+      };
+      contract.payment_methods.insert(&method.name, &method);
+    }
+  }
+
+  pub fn mpesa() -> Vec<OfferPayment> {
+    vec![OfferPayment {
+      method: "M-Pesa".to_string(),
+      instructions: "Send money over".to_string(),
+      min_amount: U128(0),
+      max_amount: U128(100 * ONE_NEAR),
+    }]
+  }
+
   #[test]
   fn test_test() {
     assert!(true);
@@ -34,7 +59,7 @@ pub mod tests {
 
     let account_id = alice();
     assert_eq!(contract.accounts.len(), 0);
-    let result = contract.register_new_account(account_id);
+    let result = contract.register_new_account(account_id, None);
 
     assert_eq!(
       result,
@@ -50,7 +75,7 @@ pub mod tests {
 
     let mut contract = Contract::new();
 
-    contract.register_new_account(alice());
+    contract.register_new_account(alice(), None);
 
     let acc = contract.get_account(alice()).unwrap();
 
@@ -68,7 +93,7 @@ pub mod tests {
     testing_env!(_context);
 
     let mut contract = Contract::new();
-    contract.register_new_account(supercode());
+    contract.register_new_account(supercode(), None);
 
     contract.contract_deposit(&supercode());
 
@@ -101,7 +126,7 @@ pub mod tests {
 
     let mut contract = Contract::new();
 
-    contract.register_new_account(supercode());
+    contract.register_new_account(supercode(), None);
     contract.contract_deposit(&supercode());
     contract.withdraw_near(U128::from(2 * ONE_NEAR));
 
@@ -132,32 +157,36 @@ pub mod tests {
     testing_env!(_context);
 
     let mut contract = Contract::new();
-    contract.register_new_account(supercode());
+    contract.register_new_account(supercode(), None);
 
     contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
 
     contract.add_offer(
-      "somestrangeid".to_string(),
+      Some("somestrangeid".to_string()),
       "buy".to_string(),
       supercode(),
       U128(10 * ONE_NEAR),
       U128(40 * ONE_NEAR),
       1.0,
-      "M-Pesa".to_string(),
+      None,
+      mpesa(),
       "KES".to_string(),
-      "Send money over".to_string()
-
+      "Send money over".to_string(),
+      None,
     );
     contract.add_offer(
-      "somestrangeid2".to_string(),
+      Some("somestrangeid2".to_string()),
       "sell".to_string(),
       supercode(),
       U128(1 * ONE_NEAR),
       U128(5 * ONE_NEAR),
       1.0,
-      "M-Pesa".to_string(),
+      None,
+      mpesa(),
       "KES".to_string(),
-      "Send money over".to_string()
+      "Send money over".to_string(),
+      None,
     );
     
     print!("{:#?}", contract);
@@ -172,4 +201,2240 @@ pub mod tests {
     //   "ERROR: supercode() ACCOUNT BALANCE MISMATCH\n"
     // );
   }
+
+  // Mock of the price oracle `get_price_data` response, $2.5 per Near
+  pub fn mock_oracle_price_data(timestamp: u64) -> Vec<u8> {
+    json!({
+      "timestamp": timestamp.to_string(),
+      "recency_duration_sec": 90,
+      "prices": [
+        { "asset_id": "wrap.testnet", "price": { "multiplier": "25000", "decimals": 28 } },
+        { "asset_id": "usdn.testnet", "price": null }
+      ]
+    })
+    .to_string()
+    .into_bytes()
+  }
+
+  pub fn set_oracle_result(block_timestamp: u64, data: Vec<u8>) {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    context.block_timestamp = block_timestamp;
+    testing_env!(
+      context,
+      VMConfig::test(),
+      RuntimeFeesConfig::test(),
+      Default::default(),
+      vec![PromiseResult::Successful(data)]
+    );
+  }
+
+  #[test]
+  fn floating_offer_freezes_oracle_rate() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+
+    contract.add_offer(
+      Some("floating".to_string()),
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      0.0,
+      Some(2.0),
+      mpesa(),
+      "USD".to_string(),
+      "Send money over".to_string(),
+      None,
+    );
+
+    let open_chat = |contract: &mut Contract, id: &str| {
+      contract.add_sell_chat(
+        Some(id.to_string()),
+        "floating".to_string(),
+        alice(),
+        U128(ONE_NEAR),
+        alice(),
+        supercode(),
+        "M-Pesa".to_string(),
+        "".to_string(),
+      )
+    };
+
+    // No price fetched yet
+    assert_ne!(open_chat(&mut contract, "chat1"), "chat1");
+
+    let now = 1_000 * 1_000_000_000;
+    set_oracle_result(now, mock_oracle_price_data(now));
+    let price = contract.on_oracle_price().unwrap();
+    assert!((price.price - 2.5).abs() < 1e-9, "ERROR: ORACLE PRICE MISMATCH");
+
+    assert_eq!(open_chat(&mut contract, "chat1"), "chat1");
+    let chat = contract.get_chat("chat1".to_string()).unwrap();
+    assert!((chat.rate - 2.55).abs() < 1e-5, "ERROR: CHAT RATE SHOULD BE ORACLE PRICE + 2%");
+
+    // Stale prices are refused, chats already opened keep their frozen rate
+    let mut context = get_context(false);
+    context.block_timestamp = now + 91 * 1_000_000_000;
+    testing_env!(context);
+    assert_ne!(open_chat(&mut contract, "chat2"), "chat2");
+    let chat = contract.get_chat("chat1".to_string()).unwrap();
+    assert!((chat.rate - 2.55).abs() < 1e-5);
+  }
+
+  #[test]
+  #[should_panic(expected = "Oracle price is stale")]
+  fn stale_oracle_price_is_rejected() {
+    let mut contract = Contract::new();
+    let now = 1_000 * 1_000_000_000;
+    set_oracle_result(now, mock_oracle_price_data(now - 120 * 1_000_000_000));
+    contract.on_oracle_price();
+  }
+
+  #[test]
+  fn chat_picks_offer_payment_method() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+
+    let mut payments = mpesa();
+    payments.push(OfferPayment {
+      method: "PayPal".to_string(),
+      instructions: "".to_string(),
+      min_amount: U128(0),
+      max_amount: U128(ONE_NEAR),
+    });
+    let add_offer = |contract: &mut Contract, payments: Vec<OfferPayment>| {
+      contract.add_offer(
+        Some("multi".to_string()),
+        "sell".to_string(),
+        supercode(),
+        U128(ONE_NEAR),
+        U128(4 * ONE_NEAR),
+        1.0,
+        None,
+        payments,
+        "KES".to_string(),
+        "".to_string(),
+        None,
+      )
+    };
+    assert_eq!(
+      add_offer(&mut contract, payments.clone()),
+      "Payment method PayPal is not supported"
+    );
+
+    payments[1] = OfferPayment {
+      method: "Bank Transfer".to_string(),
+      instructions: "Account 0123".to_string(),
+      min_amount: U128(2 * ONE_NEAR),
+      max_amount: U128(4 * ONE_NEAR),
+    };
+    assert_eq!(add_offer(&mut contract, payments), "multi");
+    assert_eq!(contract.pub_get_offer("multi".to_string()).unwrap().payments.len(), 2);
+
+    let open_chat = |contract: &mut Contract, id: &str, amount: u128, method: &str| {
+      contract.add_sell_chat(
+        Some(id.to_string()),
+        "multi".to_string(),
+        alice(),
+        U128(amount),
+        alice(),
+        supercode(),
+        method.to_string(),
+        "".to_string(),
+      )
+    };
+    assert_eq!(
+      open_chat(&mut contract, "chat1", ONE_NEAR, "PayPal"),
+      "Payment method is not accepted by this offer"
+    );
+    assert_eq!(
+      open_chat(&mut contract, "chat1", ONE_NEAR, "Bank Transfer"),
+      "Amount is outside the limits of the payment method"
+    );
+    assert_eq!(open_chat(&mut contract, "chat1", 2 * ONE_NEAR, "Bank Transfer"), "chat1");
+    let chat = contract.get_chat("chat1".to_string()).unwrap();
+    assert_eq!(chat.payment_method, "Bank Transfer");
+  }
+
+  #[test]
+  fn offer_book_filters_and_sorts() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    contract.contract_deposit(&alice());
+    add_payment_methods(&mut contract);
+
+    let offers = [
+      ("s1", supercode(), 130.0, "KES", 1),
+      ("s2", alice(), 120.0, "KES", 1),
+      ("s3", supercode(), 125.0, "KES", 3),
+      ("s4", alice(), 1.0, "USD", 1),
+    ];
+    for (id, offerer, rate, currency, min) in offers {
+      contract.add_offer(
+        Some(id.to_string()),
+        "sell".to_string(),
+        offerer,
+        U128(min * ONE_NEAR),
+        U128(5 * ONE_NEAR),
+        rate,
+        None,
+        mpesa(),
+        currency.to_string(),
+        "".to_string(),
+        None,
+      );
+    }
+    contract.update_offer_status("s2".to_string(), false);
+    contract.get_account(supercode()).unwrap().add_like();
+
+    let filter = OfferFilter {
+      offer_type: "sell".to_string(),
+      currency: "KES".to_string(),
+      payment_method: Some("M-Pesa".to_string()),
+      amount: None,
+      min_likes: None,
+      active_only: None,
+    };
+    let ids = |offers: Vec<offer::CompleteOffer>| offers.into_iter().map(|o| o.id).collect::<Vec<_>>();
+
+    let book = contract.get_offer_book(filter.clone(), Some(OfferSort::BestRate), None, None);
+    assert_eq!(ids(book), vec!["s3", "s1"], "ERROR: INACTIVE OR OTHER CURRENCY OFFERS LISTED");
+
+    let mut with_amount = filter.clone();
+    with_amount.amount = Some(U128(2 * ONE_NEAR));
+    with_amount.active_only = Some(false);
+    let book = contract.get_offer_book(with_amount, Some(OfferSort::BestRate), None, None);
+    assert_eq!(ids(book), vec!["s2", "s1"]);
+
+    let mut with_likes = filter.clone();
+    with_likes.active_only = Some(false);
+    with_likes.min_likes = Some(1);
+    let book = contract.get_offer_book(with_likes, Some(OfferSort::BestRate), Some(1), Some(1));
+    assert_eq!(ids(book), vec!["s1"]);
+
+    let mut other_method = filter;
+    other_method.payment_method = Some("Bank Transfer".to_string());
+    assert!(contract.get_offer_book(other_method, None, None, None).is_empty());
+  }
+
+  #[test]
+  fn quick_trade_takes_best_liquid_offer() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(dalmasonto(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    contract.contract_deposit(&dalmasonto());
+    add_payment_methods(&mut contract);
+
+    for (id, offerer, rate) in [("cheap", dalmasonto(), 110.0), ("fair", supercode(), 120.0)] {
+      contract.add_offer(
+        Some(id.to_string()),
+        "sell".to_string(),
+        offerer,
+        U128(ONE_NEAR),
+        U128(5 * ONE_NEAR),
+        rate,
+        None,
+        mpesa(),
+        "KES".to_string(),
+        "".to_string(),
+        None,
+      );
+    }
+    // The cheapest offerer can no longer cover the amount
+    contract.get_account(dalmasonto()).unwrap().lock(4 * ONE_NEAR);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+
+    let result = contract.quick_trade(
+      "near".to_string(),
+      "buy".to_string(),
+      U128(2 * ONE_NEAR),
+      "KES".to_string(),
+      "M-Pesa".to_string(),
+    );
+    assert_eq!(result.offer_id, "fair");
+    assert_eq!(result.rate, 120.0);
+
+    let chat = contract.get_chat(result.chat_id).unwrap();
+    assert_eq!(chat.payer, alice());
+    assert_eq!(chat.receiver, supercode());
+    assert_eq!(contract.get_account(supercode()).unwrap().locked, 2 * ONE_NEAR);
+  }
+
+  #[test]
+  fn contract_allocates_ids_and_rejects_duplicates() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+
+    let add_offer = |contract: &mut Contract, id: Option<String>| {
+      contract.add_offer(
+        id,
+        "sell".to_string(),
+        supercode(),
+        U128(ONE_NEAR),
+        U128(2 * ONE_NEAR),
+        1.0,
+        None,
+        mpesa(),
+        "KES".to_string(),
+        "".to_string(),
+        None,
+      )
+    };
+    assert_eq!(add_offer(&mut contract, None), "offer-1");
+    assert_eq!(add_offer(&mut contract, None), "offer-2");
+    assert_eq!(add_offer(&mut contract, Some("legacy".to_string())), "legacy");
+    assert_eq!(
+      add_offer(&mut contract, Some("legacy".to_string())),
+      "Id legacy is already in use"
+    );
+    assert_eq!(
+      add_offer(&mut contract, Some("offer-9".to_string())),
+      "Ids starting with offer- are reserved"
+    );
+    assert_eq!(contract.offers.len(), 3);
+
+    let chat_id = contract.add_sell_chat(
+      None,
+      "offer-1".to_string(),
+      alice(),
+      U128(ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    assert_eq!(chat_id, "chat-1");
+    assert_eq!(contract.get_transfer("transfer-1".to_string()).unwrap().amount, 5 * ONE_NEAR);
+  }
+
+  // Opens a sell chat from alice on a new supercode offer, returns what add_sell_chat returned
+  pub fn open_released_sell_chat(contract: &mut Contract, release: bool) -> String {
+    add_payment_methods(contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_sell_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    if release {
+      contract.mark_as_received(chat_id.clone());
+    }
+    chat_id
+  }
+
+  #[test]
+  fn released_chat_rating_updates_reputation() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, true);
+    assert!(contract.get_chat(chat_id.clone()).unwrap().released);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    contract.rate_chat(chat_id.clone(), true, Some("Fast release".to_string()));
+
+    let info = contract.acc_pub_info(supercode()).unwrap();
+    assert_eq!(info.likes, 1);
+    assert_eq!(info.completed_trades, 1);
+    assert_eq!(info.score, Some(100.0));
+    assert_eq!(info.completion_rate, Some(100.0));
+    assert_eq!(info.cancellation_rate, Some(0.0));
+    assert_eq!(info.avg_release_time, Some(0));
+
+    let ratings = contract.get_account_ratings(supercode());
+    assert_eq!(ratings.len(), 1);
+    assert_eq!(ratings[0].comment, Some("Fast release".to_string()));
+    assert!(contract.get_chat(chat_id).unwrap().payer_has_rated);
+  }
+
+  #[test]
+  #[should_panic(expected = "Only released chats can be rated")]
+  fn unreleased_chat_cannot_be_rated() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+    contract.rate_chat(chat_id, false, None);
+  }
+
+  #[test]
+  #[should_panic(expected = "Only chat participants can rate the chat")]
+  fn non_participant_cannot_rate_chat() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, true);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.rate_chat(chat_id, false, None);
+  }
+
+  #[test]
+  fn blocked_accounts_cannot_open_chats() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+
+    // supercode blocks alice, alice can no longer open chats on supercode offers
+    contract.block_account(alice());
+    contract.block_account(alice());
+    assert_eq!(contract.acc_pub_info(alice()).unwrap().blocked_by, 1);
+    assert_eq!(contract.get_blocked_accounts(supercode()), vec![alice()]);
+    assert_eq!(
+      open_released_sell_chat(&mut contract, false),
+      "You can't open a chat with this account"
+    );
+
+    contract.unblock_account(alice());
+    assert_eq!(contract.acc_pub_info(alice()).unwrap().blocked_by, 0);
+    assert!(!contract.is_blocked_between(alice(), supercode()));
+    assert_eq!(open_released_sell_chat(&mut contract, false), "chat-1");
+  }
+
+  #[test]
+  fn offer_requirements_restrict_counterparties() {
+    let mut _context = get_context(false);
+    _context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(_context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+
+    let mut add_offer = |requirements: OfferRequirements| {
+      contract.add_offer(
+        None,
+        "sell".to_string(),
+        supercode(),
+        U128(ONE_NEAR),
+        U128(ONE_NEAR),
+        1.0,
+        None,
+        mpesa(),
+        "KES".to_string(),
+        "".to_string(),
+        Some(requirements),
+      )
+    };
+    let experienced = add_offer(OfferRequirements {
+      min_completed_trades: Some(1),
+      ..Default::default()
+    });
+    let aged = add_offer(OfferRequirements {
+      min_account_age_sec: Some(60),
+      ..Default::default()
+    });
+    let trusted = add_offer(OfferRequirements {
+      allowlist: Some(vec![alice()]),
+      ..Default::default()
+    });
+    let liked = add_offer(OfferRequirements {
+      min_like_ratio: Some(0.5),
+      ..Default::default()
+    });
+    assert_eq!(
+      contract.pub_get_offer(experienced.clone()).unwrap().requirements.min_completed_trades,
+      Some(1)
+    );
+
+    let open_chat = |contract: &mut Contract, offer_id: String| {
+      contract.add_sell_chat(
+        None,
+        offer_id,
+        alice(),
+        U128(ONE_NEAR),
+        alice(),
+        supercode(),
+        "M-Pesa".to_string(),
+        "".to_string(),
+      )
+    };
+    assert_eq!(
+      open_chat(&mut contract, experienced),
+      "You have not completed enough trades for this offer"
+    );
+    assert_eq!(open_chat(&mut contract, aged.clone()), "Your account is too new for this offer");
+    assert_eq!(
+      open_chat(&mut contract, liked),
+      "Your like ratio is too low for this offer"
+    );
+    assert_eq!(open_chat(&mut contract, trusted), "chat-1");
+
+    let mut context = get_context(false);
+    context.block_timestamp = 61 * 1_000_000_000;
+    testing_env!(context);
+    assert_eq!(open_chat(&mut contract, aged), "chat-2");
+  }
+
+  #[test]
+  fn merchant_bond_raises_limits_and_can_be_slashed() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 50;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    assert_eq!(contract.get_offer_limit(supercode()), U128(100 * ONE_NEAR));
+
+    contract.stake_bond();
+    let info = contract.acc_pub_info(supercode()).unwrap();
+    assert!(info.verified_merchant, "ERROR: 50 NEAR BOND SHOULD VERIFY THE MERCHANT");
+    assert_eq!(contract.get_offer_limit(supercode()), U128(600 * ONE_NEAR));
+
+    // A chat between alice and supercode is ruled against supercode
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    contract.request_bond_withdrawal(U128(10 * ONE_NEAR));
+    assert!(!contract.is_verified_merchant(supercode()));
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    contract.extend_guardians(vec![dalmasonto()]);
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.slash_bond(chat_id, supercode(), U128(15 * ONE_NEAR), Some(alice()));
+
+    let bond = contract.get_bond(supercode()).unwrap();
+    assert_eq!(bond.unstaking, 0);
+    assert_eq!(bond.staked, 35 * ONE_NEAR);
+    assert_eq!(contract.get_account(alice()).unwrap().balance, 15 * ONE_NEAR);
+  }
+
+  #[test]
+  #[should_panic(expected = "Bond is still in its cooldown")]
+  fn bond_withdrawal_waits_for_cooldown() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 10;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.stake_bond();
+    contract.request_bond_withdrawal(U128(10 * ONE_NEAR));
+    contract.withdraw_bond();
+  }
+
+  pub fn add_guardian(contract: &mut Contract, guardian: AccountId) {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    contract.extend_guardians(vec![guardian]);
+  }
+
+  #[test]
+  #[should_panic(expected = "Chats are paused")]
+  fn guardian_pause_blocks_new_chats() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.set_paused(Subsystem::Chats, true);
+    assert!(contract.is_paused(Subsystem::Chats));
+    assert!(!contract.is_paused(Subsystem::Offers));
+
+    testing_env!(get_context(false));
+    open_released_sell_chat(&mut contract, false);
+  }
+
+  #[test]
+  #[should_panic(expected = "Swaps are paused")]
+  fn paused_swaps_reject_quick_trades() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+    contract.add_offer(
+      Some("offer".to_string()),
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(5 * ONE_NEAR),
+      120.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.set_paused(Subsystem::Swaps, true);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    contract.quick_trade(
+      "near".to_string(),
+      "buy".to_string(),
+      U128(ONE_NEAR),
+      "KES".to_string(),
+      "M-Pesa".to_string(),
+    );
+  }
+
+  #[test]
+  fn emergency_mode_keeps_refunds_and_withdrawals_open() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.set_emergency(true);
+    assert!(contract.is_paused(Subsystem::Deposits));
+    assert!(contract.is_paused(Subsystem::Chats));
+    assert!(!contract.is_paused(Subsystem::TokenWithdrawals));
+
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+    assert_eq!(contract.cancel_chat(chat_id), "chat canceled");
+    contract.withdraw_near(U128(5 * ONE_NEAR));
+    assert_eq!(contract.get_account(supercode()).unwrap().balance, 0);
+  }
+
+  pub fn usdc_metadata() -> TokenMetadata {
+    TokenMetadata {
+      address: "usdc.testnet".parse().unwrap(),
+      name: "USD Coin".to_string(),
+      symbol: "USDC".to_string(),
+      icon: "".to_string(),
+      decimals: 6,
+    }
+  }
+
+  // Runs a queued admin action as `caller` once the default delay has passed
+  pub fn execute_after_delay(contract: &mut Contract, caller: AccountId, id: String) {
+    let mut context = get_context(false);
+    context.predecessor_account_id = caller;
+    context.block_timestamp = crate::owner::DEFAULT_ADMIN_DELAY_SEC * 1_000_000_000;
+    testing_env!(context);
+    contract.execute_admin_action(id);
+  }
+
+  #[test]
+  fn owner_grants_roles_for_admin_methods() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    let ids = vec![
+      contract.grant_role(Role::TokenManager, alice()),
+      contract.grant_role(Role::Pauser, alice()),
+      contract.grant_role(Role::Admin, dalmasonto()),
+    ];
+    for id in ids {
+      execute_after_delay(&mut contract, master(), id);
+    }
+    assert_eq!(contract.get_role_members(Role::TokenManager), vec![alice()]);
+    assert_eq!(contract.get_account_roles(alice()), vec![Role::TokenManager, Role::Pauser]);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    contract.add_token("usdc.testnet".parse().unwrap(), usdc_metadata());
+    contract.set_paused(Subsystem::Swaps, true);
+    assert_eq!(contract.get_tokens().len(), 1);
+
+    // Admins pass every role check
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    let id = contract.update_send_cost(0.1);
+    execute_after_delay(&mut contract, dalmasonto(), id);
+    assert_eq!(contract.get_send_cost(), 0.1);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    contract.revoke_role(Role::TokenManager, alice());
+    assert!(!contract.has_role(Role::TokenManager, alice()));
+  }
+
+  #[test]
+  fn guardians_cancel_queued_admin_actions() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.extend_guardians(vec![dalmasonto()]);
+    let fee_id = contract.update_send_cost(0.5);
+    let owner_id = contract.propose_new_owner(alice());
+    assert_eq!(contract.get_send_cost(), 0.05, "ERROR: FEE CHANGE SHOULD WAIT FOR THE DELAY");
+
+    let pending = contract.get_pending_admin_actions();
+    assert_eq!(pending.len(), 2);
+    assert_eq!(pending[0].executable_at, crate::owner::DEFAULT_ADMIN_DELAY_SEC * 1_000_000_000);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.cancel_admin_action(fee_id);
+
+    execute_after_delay(&mut contract, master(), owner_id);
+    assert_eq!(contract.get_send_cost(), 0.05);
+    assert!(contract.get_pending_admin_actions().is_empty());
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    contract.accept_ownership();
+    assert_eq!(contract.owner(), alice());
+  }
+
+  #[test]
+  #[should_panic(expected = "Admin action is still in its delay")]
+  fn admin_actions_wait_for_delay() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    let id = contract.update_send_cost(0.5);
+    contract.execute_admin_action(id);
+  }
+
+  #[test]
+  #[should_panic(expected = "This method requires the TokenManager role")]
+  fn admin_methods_reject_accounts_without_role() {
+    testing_env!(get_context(false));
+    let mut contract = Contract::new();
+    contract.add_token("usdc.testnet".parse().unwrap(), usdc_metadata());
+  }
+
+  // Writes the state of the baseline deployment, with two accounts and an
+  // offer and a chat of each kind
+  fn write_baseline_state() {
+    use crate::upgrade::{AccountV0, ChatV0, ContractV0, OfferV0, TokenChatV0, TokenOfferV0};
+    use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet, Vector};
+    use std::collections::HashMap;
+
+    let usdn: AccountId = "usdn.testnet".parse().unwrap();
+    let account = |id: AccountId, balance: u128, locked: u128| AccountV0 {
+      id,
+      balance,
+      locked,
+      likes: 2,
+      dislikes: 0,
+      tokens: HashMap::new(),
+      locked_tokens: HashMap::new(),
+      blocked_by: 0,
+      created_on: 7,
+    };
+    let mut old = ContractV0 {
+      owner_id: master(),
+      proposed_owner_id: master(),
+      guardians: UnorderedSet::new(b"g".to_vec()),
+      accounts: HashMap::new(),
+      trades: Vector::new(b"a".to_vec()),
+      transfers: Vector::new(StorageKey::Transfers),
+      tokenswaps: LookupMap::new(b"b".to_vec()),
+      offers: UnorderedMap::new(b"c".to_vec()),
+      tokenoffers: UnorderedMap::new(b"d".to_vec()),
+      chats: UnorderedMap::new(b"e".to_vec()),
+      tokenchats: UnorderedMap::new(b"f".to_vec()),
+      transfer_cost: 0,
+      send_cost: 0.05,
+      tokens: UnorderedMap::new(b"h".to_vec()),
+      whitelistedtokens: UnorderedMap::new(b"k".to_vec()),
+      payment_methods: UnorderedMap::new(b"i".to_vec()),
+      revenue: 5,
+      revenue_usd: 0.0,
+      revenues: UnorderedSet::new(b"l".to_vec()),
+    };
+    old.guardians.insert(&supercode());
+    old.accounts.insert(supercode(), account(supercode(), 3 * ONE_NEAR, ONE_NEAR));
+    let mut alice_acc = account(alice(), 0, 0);
+    alice_acc.locked_tokens.insert(usdn.clone(), 50);
+    alice_acc.tokens.insert(usdn.clone(), 100);
+    old.accounts.insert(alice(), alice_acc);
+    old.offers.insert(
+      &"offer-1".to_string(),
+      &OfferV0 {
+        id: "offer-1".to_string(),
+        offer_type: "sell".to_string(),
+        offerer: supercode(),
+        min_amount: 1,
+        max_amount: 10 * ONE_NEAR,
+        offer_rate: 130.0,
+        active: true,
+        payment: "M-Pesa".to_string(),
+        currency: "KES".to_string(),
+        instructions: "Pay to 0700".to_string(),
+      },
+    );
+    old.chats.insert(
+      &"chat-1".to_string(),
+      &ChatV0 {
+        id: "chat-1".to_string(),
+        offer_id: "offer-1".to_string(),
+        owner: alice(),
+        offerer: supercode(),
+        amount: ONE_NEAR,
+        trade_cost: 0,
+        trade_cost_usd: 0.0,
+        started_at: 1,
+        ended_at: None,
+        active: true,
+        payer: alice(),
+        receiver: supercode(),
+        paid: false,
+        received: false,
+        canceled: false,
+        released: false,
+        payer_has_rated: false,
+        receiver_has_rated: false,
+        payment_msg: "Sending now".to_string(),
+        created_on: 1,
+        updated_on: None,
+      },
+    );
+    old.tokenoffers.insert(
+      &"token-offer-1".to_string(),
+      &TokenOfferV0 {
+        id: "token-offer-1".to_string(),
+        offer_type: "sell".to_string(),
+        offerer: alice(),
+        min_amount: 1,
+        max_amount: 100,
+        offer_rate: 1.0,
+        active: true,
+        token: usdn.clone(),
+        payment: "Bank".to_string(),
+        currency: "USD".to_string(),
+        instructions: String::new(),
+        created_on: 3,
+        updated_on: None,
+      },
+    );
+    old.tokenchats.insert(
+      &"token-chat-1".to_string(),
+      &TokenChatV0 {
+        id: "token-chat-1".to_string(),
+        offer_id: "token-offer-1".to_string(),
+        token_id: usdn.clone(),
+        owner: supercode(),
+        offerer: alice(),
+        amount: 50,
+        trade_cost: 0,
+        trade_cost_usd: 0.0,
+        started_at: 3,
+        ended_at: None,
+        active: true,
+        payer: supercode(),
+        receiver: alice(),
+        paid: false,
+        received: false,
+        canceled: false,
+        released: false,
+        payer_has_rated: false,
+        receiver_has_rated: false,
+        payment_msg: String::new(),
+        created_on: 3,
+        updated_on: None,
+      },
+    );
+    env::state_write(&old);
+  }
+
+  #[test]
+  fn migrate_converts_the_baseline_state() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    write_baseline_state();
+    let usdn: AccountId = "usdn.testnet".parse().unwrap();
+
+    let mut contract = Contract::migrate();
+    assert!(contract.pub_get_account(supercode()).is_none());
+    assert!(!contract.migrate_state(3));
+    assert!(contract.migration.is_some());
+    assert!(contract.migrate_state(10));
+    assert!(contract.migration.is_none());
+    let acc = contract.pub_get_account(supercode()).unwrap();
+    assert_eq!((acc.balance, acc.locked, acc.likes, acc.created_on), (3 * ONE_NEAR, ONE_NEAR, 2, 7));
+    assert!(acc.blocked_accounts.is_empty());
+    assert_eq!(acc.bond.staked, 0);
+    assert_eq!(contract.pub_get_account(alice()).unwrap().locked_tokens.get(&usdn).unwrap(), 50);
+    assert!(contract.guardians.contains(&supercode()));
+    assert_eq!(contract.revenue, 5);
+
+    let offer = contract.get_offer("offer-1".to_string()).unwrap();
+    assert_eq!(offer.payments.len(), 1);
+    assert_eq!(offer.get_payment("M-Pesa").unwrap().instructions, "Pay to 0700");
+    assert_eq!(offer.get_payment("M-Pesa").unwrap().max_amount, U128(10 * ONE_NEAR));
+    let filter = OfferFilter {
+      offer_type: "sell".to_string(),
+      currency: "KES".to_string(),
+      payment_method: None,
+      amount: None,
+      min_likes: None,
+      active_only: None,
+    };
+    let book = contract.get_offer_book(filter, Some(OfferSort::BestRate), None, None);
+    assert_eq!(book.len(), 1);
+
+    let chat = contract.get_chat("chat-1".to_string()).unwrap();
+    assert_eq!((chat.payer, chat.rate, chat.released_amount), (alice(), 130.0, 0));
+    assert_eq!(chat.payment_method, "M-Pesa");
+    assert_eq!(contract.get_offer_chats("offer-1".to_string()).len(), 1);
+
+    let token_offer = contract.get_token_offer("token-offer-1".to_string()).unwrap();
+    assert!(token_offer.get_payment("Bank").is_some());
+    let token_chat = contract.get_token_chat("token-chat-1".to_string()).unwrap();
+    assert_eq!(token_chat.payment_method, "Bank");
+    assert_eq!(contract.get_token_offer_chats("token-offer-1".to_string()).len(), 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "State migration is still running")]
+  fn registrations_wait_for_the_migration() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    write_baseline_state();
+
+    let mut contract = Contract::migrate();
+    contract.migrate_state(1);
+    contract.register_new_account(dalmasonto(), None);
+  }
+
+  #[test]
+  fn chats_are_indexed_by_offer() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    let chats = contract.get_offer_chats("offer-1".to_string());
+    assert_eq!(chats.len(), 1);
+    assert_eq!(chats[0].id, chat_id);
+    assert!(contract.get_offer_chats("offer-2".to_string()).is_empty());
+  }
+
+  #[test]
+  fn close_account_requires_settled_chats_and_offers() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    context.account_balance = ONE_NEAR; // Pays the yocto attached to ft_transfer
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+    assert_eq!(
+      contract.close_account(),
+      "Finish or cancel your open chats before closing the account"
+    );
+
+    contract.cancel_chat(chat_id);
+    assert_eq!(contract.close_account(), "Deactivate your offers before closing the account");
+
+    contract.update_offer_status("offer-1".to_string(), false);
+    contract.get_account(supercode()).unwrap().deposit_tokens(usdn(), 500);
+    let transfers = contract.get_transfers_len();
+    let allowance = contract.get_allowance(supercode(), None).withdrawal_remaining.0;
+    assert_eq!(contract.close_account(), "Account closed");
+    assert!(contract.pub_get_account(supercode()).is_none());
+    assert!(contract.get_offer("offer-1".to_string()).is_none());
+    assert_eq!(contract.get_transfers_len(), transfers + 1);
+    // The closing withdrawal counts against the daily limit
+    assert_eq!(
+      contract.get_allowance(supercode(), None).withdrawal_remaining.0,
+      allowance - 5 * ONE_NEAR
+    );
+  }
+
+  #[test]
+  fn failed_token_withdrawal_on_close_is_credited_back() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context, VMConfig::test(), RuntimeFeesConfig::test(), Default::default(), vec![PromiseResult::Failed]);
+
+    let mut contract = Contract::new();
+    let mut closed = crate::account::Account::new(alice());
+    closed.created_on = 42;
+    closed.likes = 3;
+    contract.on_close_account_token(closed, usdn(), U128(500));
+    let acc = contract.pub_get_account(alice()).unwrap();
+    assert_eq!(acc.get_token_balance(usdn()), 500);
+    assert_eq!((acc.created_on, acc.likes), (42, 3));
+  }
+
+  #[test]
+  fn token_withdrawals_lock_the_amount_until_settled() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    context.account_balance = ONE_NEAR; // Pays the yocto attached to ft_transfer
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(alice(), None);
+    contract.get_account(alice()).unwrap().deposit_tokens(usdn(), 500);
+    contract.withdraw_named_asset(usdn(), U128(200));
+    let acc = contract.pub_get_account(alice()).unwrap();
+    assert_eq!(acc.get_token_balance(usdn()), 300);
+    assert_eq!(acc.locked_tokens.get(&usdn()), Some(200));
+  }
+
+  #[test]
+  #[should_panic(expected = "is denylisted")]
+  fn denylisted_accounts_cant_withdraw_tokens() {
+    testing_env!(get_context(false));
+    let mut contract = Contract::new();
+    contract.register_new_account(alice(), None);
+    contract.get_account(alice()).unwrap().deposit_tokens(usdn(), 500);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(alice(), crate::denylist::DenyReason::Fraud, None);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    contract.withdraw_named_asset(usdn(), U128(200));
+  }
+
+  #[test]
+  fn new_accounts_are_limited_by_tier() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 60;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(20 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let open_chat = |contract: &mut Contract, amount: u128| {
+      contract.add_sell_chat(
+        None,
+        offer_id.clone(),
+        alice(),
+        U128(amount),
+        alice(),
+        supercode(),
+        "M-Pesa".to_string(),
+        "".to_string(),
+      )
+    };
+    assert_eq!(
+      open_chat(&mut contract, 11 * ONE_NEAR),
+      format!("Amount is above the per-trade limit of {}", alice())
+    );
+    assert_eq!(open_chat(&mut contract, 10 * ONE_NEAR), "chat-1");
+
+    let allowance = contract.get_allowance(supercode(), None);
+    assert_eq!(allowance.tier, crate::limits::Tier::New);
+    assert_eq!(allowance.trade_remaining, U128(40 * ONE_NEAR));
+    assert_eq!(allowance.withdrawal_remaining, U128(50 * ONE_NEAR));
+  }
+
+  #[test]
+  #[should_panic(expected = "Amount is above your daily withdrawal limit")]
+  fn withdrawals_are_capped_per_day() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 60;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.contract_deposit(&supercode());
+    contract.withdraw_near(U128(30 * ONE_NEAR));
+    contract.withdraw_near(U128(30 * ONE_NEAR));
+  }
+
+  #[test]
+  fn denylisted_accounts_are_frozen() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(supercode(), crate::denylist::DenyReason::Fraud, Some("Chargebacks".to_string()));
+    assert!(contract.is_denylisted(supercode()));
+    assert!(!contract.get_offer("offer-1".to_string()).unwrap().active);
+
+    testing_env!(get_context(false));
+    let denied = format!("Account {} is denylisted", supercode());
+    let chat = contract.add_sell_chat(
+      None,
+      "offer-1".to_string(),
+      alice(),
+      U128(ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    assert_eq!(chat, denied);
+    assert_eq!(contract.close_account(), denied);
+
+    // Only arbiters can move the frozen escrow
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    assert_eq!(contract.cancel_chat(chat_id), "chat canceled");
+  }
+
+  #[test]
+  #[should_panic(expected = "This method requires the Arbiter role")]
+  fn denylisted_escrow_needs_arbiter() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(alice(), crate::denylist::DenyReason::Investigation, None);
+
+    testing_env!(get_context(false));
+    contract.cancel_chat(chat_id);
+  }
+
+  #[test]
+  fn arbiters_release_frozen_chats() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(alice(), crate::denylist::DenyReason::Investigation, None);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    assert_eq!(contract.resolve_frozen_chat(chat_id.clone(), true), "chat released");
+    let offerer = contract.pub_get_account(supercode()).unwrap();
+    assert_eq!((offerer.balance, offerer.locked), (3 * ONE_NEAR, 0));
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 2 * ONE_NEAR);
+    assert!(contract.get_chat(chat_id).unwrap().released);
+  }
+
+  #[test]
+  #[should_panic(expected = "This method requires the Arbiter role")]
+  fn participants_cant_resolve_frozen_chats() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(alice(), crate::denylist::DenyReason::Investigation, None);
+
+    // supercode holds the escrow but isn't an arbiter
+    testing_env!(get_context(false));
+    contract.resolve_frozen_chat(chat_id, false);
+  }
+
+  #[test]
+  #[should_panic(expected = "Only the chat participants or an arbiter can cancel it")]
+  fn strangers_cant_cancel_chats() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.cancel_chat(chat_id);
+  }
+
+  #[test]
+  fn referrer_earns_share_of_trade_fees() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 1.0;
+    contract.register_new_account(dalmasonto(), None);
+    assert_eq!(
+      contract.register_new_account(supercode(), Some(master())),
+      "Referrer is not registered"
+    );
+    contract.register_new_account(supercode(), Some(dalmasonto()));
+    // supercode registers alice, the referrer it names is ignored
+    contract.register_new_account(alice(), Some(dalmasonto()));
+    contract.contract_deposit(&supercode());
+    assert_eq!(contract.get_referrer(supercode()), Some(dalmasonto()));
+    assert_eq!(contract.get_referrer(alice()), None);
+    assert_eq!(contract.get_referral_stats(dalmasonto()).referred, 1);
+
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_sell_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    contract.mark_as_received(chat_id.clone());
+    assert_eq!(contract.get_chat(chat_id).unwrap().fee_collected, ONE_NEAR);
+
+    let stats = contract.get_referral_stats(dalmasonto());
+    assert_eq!(stats.referred, 1);
+    assert_eq!(stats.earned, ONE_NEAR / 5);
+    assert_eq!(contract.pub_get_account(dalmasonto()).unwrap().balance, ONE_NEAR / 5);
+    assert_eq!(contract.revenue, ONE_NEAR * 4 / 5);
+  }
+
+  #[test]
+  fn volume_tiers_discount_trade_fees() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 1.0;
+    contract.oracle_price = Some(OraclePrice {
+      asset_id: "near".to_string(),
+      price: 1.0,
+      timestamp: 0,
+    });
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    contract.record_released_volume(NEAR_ASSET, 10_000 * ONE_NEAR, [&supercode(), &alice()]);
+
+    let tier = contract.get_fee_tier(supercode(), None);
+    assert_eq!(tier.tier, 2);
+    assert_eq!(tier.discount_bps, 2_500);
+    assert_eq!(tier.effective_fee, U128(ONE_NEAR * 3 / 4));
+
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_sell_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    let chat = contract.get_chat(chat_id).unwrap();
+    assert_eq!(chat.trade_cost, ONE_NEAR * 3 / 4);
+    assert_eq!(chat.trade_cost_usd, 0.75);
+
+    // Volume older than 30 days no longer counts
+    let mut context = get_context(false);
+    context.block_timestamp = 30 * 24 * 60 * 60 * 1_000_000_000;
+    testing_env!(context);
+    assert_eq!(contract.get_fee_tier(supercode(), None).discount_bps, 0);
+  }
+
+  #[test]
+  fn invoices_are_paid_from_balance() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    let invoice_id = contract.create_invoice(
+      NEAR_ASSET.to_string(),
+      U128(2 * ONE_NEAR),
+      "Design work".to_string(),
+      3600,
+      Some(supercode()),
+    );
+    assert_eq!(invoice_id, "invoice-1");
+    assert_eq!(contract.pay_invoice(invoice_id.clone()), "You can't pay your own invoice");
+
+    testing_env!(get_context(false));
+    let transfers = contract.get_transfers_len();
+    assert_eq!(contract.pay_invoice(invoice_id.clone()), "Invoice paid");
+    assert_eq!(contract.pay_invoice(invoice_id.clone()), "Invoice is already paid");
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().balance, 3 * ONE_NEAR);
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 2 * ONE_NEAR);
+    assert_eq!(contract.get_transfers_len(), transfers + 1);
+    let invoice = contract.get_invoice(invoice_id).unwrap();
+    assert_eq!(invoice.status, crate::invoice::InvoiceStatus::Paid);
+    assert_eq!(invoice.paid_by, Some(supercode()));
+  }
+
+  #[test]
+  fn expired_invoices_cant_be_paid() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    let invoice_id = contract.create_invoice(NEAR_ASSET.to_string(), U128(ONE_NEAR), "".to_string(), 60, None);
+
+    let mut context = get_context(false);
+    context.block_timestamp = 61 * 1_000_000_000;
+    testing_env!(context);
+    assert_eq!(contract.pay_invoice(invoice_id.clone()), "Invoice has expired");
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().balance, 5 * ONE_NEAR);
+    assert_eq!(
+      contract.invoices.get(&invoice_id).unwrap().status,
+      crate::invoice::InvoiceStatus::Expired
+    );
+  }
+
+  #[test]
+  fn invoices_are_paid_with_ft_transfer_call() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.add_token(usdn(), usdc_metadata());
+    contract.register_new_account(alice(), None);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    let invoice_id = contract.create_invoice(usdn().to_string(), U128(500), "".to_string(), 3600, None);
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = usdn();
+    testing_env!(context);
+    let msg = json!({ "invoice_id": invoice_id }).to_string();
+    match contract.ft_on_transfer(supercode(), U128(600), msg.clone()) {
+      near_sdk::PromiseOrValue::Value(refund) => assert_eq!(refund, U128(100)),
+      _ => panic!("Invoice payments settle without a promise"),
+    }
+    assert_eq!(contract.pub_get_account(alice()).unwrap().get_token_balance(usdn()), 500);
+    assert_eq!(
+      contract.get_invoice(invoice_id).unwrap().status,
+      crate::invoice::InvoiceStatus::Paid
+    );
+
+    // A second payment is refunded in full
+    match contract.ft_on_transfer(supercode(), U128(500), msg) {
+      near_sdk::PromiseOrValue::Value(refund) => assert_eq!(refund, U128(500)),
+      _ => panic!("Invoice payments settle without a promise"),
+    }
+  }
+
+  #[test]
+  fn batch_transfers_are_all_or_nothing() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.register_new_account(dalmasonto(), None);
+    contract.contract_deposit(&supercode());
+    let batch = |amount: u128| {
+      vec![
+        crate::transfer::BatchRecipient {
+          account_id: alice(),
+          amount: U128(ONE_NEAR),
+        },
+        crate::transfer::BatchRecipient {
+          account_id: dalmasonto(),
+          amount: U128(amount),
+        },
+      ]
+    };
+
+    // The fee doesn't fit next to the amounts, nobody is paid
+    let transfers = contract.get_transfers_len();
+    assert_eq!(
+      contract.batch_transfer(NEAR_ASSET.to_string(), batch(4 * ONE_NEAR)),
+      "You do not have enough balance for the batch and its fee"
+    );
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 0);
+    assert_eq!(contract.get_transfers_len(), transfers);
+
+    assert_eq!(contract.batch_transfer(NEAR_ASSET.to_string(), batch(2 * ONE_NEAR)), "Sent to 2 recipients");
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, ONE_NEAR);
+    assert_eq!(contract.pub_get_account(dalmasonto()).unwrap().balance, 2 * ONE_NEAR);
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().balance, 2 * ONE_NEAR - contract.send_fee());
+    assert_eq!(contract.get_transfers_len(), transfers + 2);
+    assert_eq!(contract.revenue, contract.send_fee());
+  }
+
+  #[test]
+  fn due_schedules_are_paid_by_the_crank() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let mut context = get_context(false);
+    context.attached_deposit = SCHEDULE_STORAGE_DEPOSIT;
+    testing_env!(context);
+    let schedule_id =
+      contract.create_schedule(alice(), NEAR_ASSET.to_string(), U128(2 * ONE_NEAR), 3600, 0, Some(3 * 3600));
+    assert_eq!(schedule_id, "schedule-1");
+
+    // Anyone can run the crank, only due runs are paid
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    assert_eq!(contract.execute_due_schedules(None), 1);
+    assert_eq!(contract.execute_due_schedules(None), 0);
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 2 * ONE_NEAR);
+
+    let mut context = get_context(false);
+    context.block_timestamp = 3600 * 1_000_000_000;
+    testing_env!(context.clone());
+    assert_eq!(contract.execute_due_schedules(None), 1);
+
+    // The payer is out of balance, the run is skipped
+    context.block_timestamp = 2 * 3600 * 1_000_000_000;
+    testing_env!(context.clone());
+    assert_eq!(contract.execute_due_schedules(None), 1);
+    let schedule = contract.get_schedule(schedule_id.clone()).unwrap();
+    assert_eq!((schedule.executions, schedule.missed), (2, 1));
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().balance, ONE_NEAR);
+
+    // Cancelling drops the schedule and gives the storage deposit back
+    assert_eq!(contract.cancel_schedule(schedule_id.clone()), "Schedule cancelled");
+    assert!(contract.get_schedule(schedule_id).is_none());
+    assert_eq!(
+      contract.pub_get_account(supercode()).unwrap().balance,
+      ONE_NEAR + SCHEDULE_STORAGE_DEPOSIT
+    );
+    context.block_timestamp = 3 * 3600 * 1_000_000_000;
+    testing_env!(context);
+    assert_eq!(contract.execute_due_schedules(None), 0);
+  }
+
+  #[test]
+  fn late_crank_counts_missed_runs_and_drops_ended_schedules() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let mut context = get_context(false);
+    context.attached_deposit = SCHEDULE_STORAGE_DEPOSIT;
+    testing_env!(context);
+    let schedule_id =
+      contract.create_schedule(alice(), NEAR_ASSET.to_string(), U128(ONE_NEAR), 3600, 0, Some(10 * 3600));
+
+    // Three runs passed before anyone cranked, they are not paid later
+    let mut context = get_context(false);
+    context.block_timestamp = 7 * 1800 * 1_000_000_000;
+    testing_env!(context.clone());
+    assert_eq!(contract.execute_due_schedules(None), 1);
+    assert_eq!(contract.execute_due_schedules(None), 0);
+    let schedule = contract.get_schedule(schedule_id.clone()).unwrap();
+    assert_eq!((schedule.executions, schedule.missed), (1, 3));
+    assert_eq!(schedule.next_run_at, 4 * 3600 * 1_000_000_000);
+
+    // Past the end the last due run is paid and the schedule is dropped
+    context.block_timestamp = 11 * 3600 * 1_000_000_000;
+    testing_env!(context);
+    assert_eq!(contract.execute_due_schedules(None), 1);
+    assert!(contract.get_schedule(schedule_id).is_none());
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 2 * ONE_NEAR);
+    assert_eq!(
+      contract.pub_get_account(supercode()).unwrap().balance,
+      3 * ONE_NEAR + SCHEDULE_STORAGE_DEPOSIT
+    );
+  }
+
+  #[test]
+  #[should_panic(expected = "Interval must be at least 3600 seconds")]
+  fn schedules_below_the_minimum_interval_are_rejected() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let mut context = get_context(false);
+    context.attached_deposit = SCHEDULE_STORAGE_DEPOSIT;
+    testing_env!(context);
+    contract.create_schedule(
+      alice(),
+      NEAR_ASSET.to_string(),
+      U128(ONE_NEAR),
+      MIN_SCHEDULE_INTERVAL_SEC - 1,
+      0,
+      None,
+    );
+  }
+
+  #[test]
+  fn milestone_escrow_releases_tranches_and_resolves_disputes() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let milestone = |amount: u128| crate::milestone::MilestoneInput {
+      description: "".to_string(),
+      amount: U128(amount),
+    };
+    let escrow_id = contract.create_milestone_escrow(
+      alice(),
+      NEAR_ASSET.to_string(),
+      vec![milestone(ONE_NEAR), milestone(2 * ONE_NEAR)],
+      3600,
+      7 * 24 * 3600,
+    );
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().locked, 3 * ONE_NEAR);
+    assert_eq!(contract.approve_milestone(escrow_id.clone(), 0), "Milestone is not submitted");
+
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context.clone());
+    assert_eq!(contract.submit_milestone(escrow_id.clone(), 0), "Milestone submitted");
+    assert_eq!(contract.claim_milestone(escrow_id.clone(), 0), "Milestone is still in review");
+
+    testing_env!(get_context(false));
+    assert_eq!(contract.approve_milestone(escrow_id.clone(), 0), "Milestone released");
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, ONE_NEAR);
+
+    testing_env!(context);
+    assert_eq!(contract.submit_milestone(escrow_id.clone(), 1), "Milestone submitted");
+    assert_eq!(contract.dispute_milestone_escrow(escrow_id.clone()), "Escrow disputed");
+    assert_eq!(contract.claim_milestone(escrow_id.clone(), 1), "Escrow is not active");
+
+    // The guardian sends the disputed tranche back to the client
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.resolve_milestone_dispute(escrow_id.clone(), vec![true, false]);
+    let client = contract.pub_get_account(supercode()).unwrap();
+    assert_eq!((client.balance, client.locked), (4 * ONE_NEAR, 0));
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, ONE_NEAR);
+    assert_eq!(
+      contract.get_milestone_escrow(escrow_id).unwrap().status,
+      crate::milestone::EscrowStatus::Completed
+    );
+  }
+
+  #[test]
+  fn arbiters_settle_frozen_milestone_escrows() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let milestone = |amount: u128| crate::milestone::MilestoneInput {
+      description: "".to_string(),
+      amount: U128(amount),
+    };
+    let escrow_id = contract.create_milestone_escrow(
+      alice(),
+      NEAR_ASSET.to_string(),
+      vec![milestone(ONE_NEAR), milestone(2 * ONE_NEAR)],
+      3600,
+      7 * 24 * 3600,
+    );
+
+    // Denylisting the provider freezes the escrow before anyone disputed it
+    add_guardian(&mut contract, dalmasonto());
+    let mut context = get_context(false);
+    context.predecessor_account_id = dalmasonto();
+    testing_env!(context);
+    contract.deny_account(alice(), crate::denylist::DenyReason::Fraud, None);
+    contract.resolve_milestone_dispute(escrow_id.clone(), vec![false, false]);
+
+    let client = contract.pub_get_account(supercode()).unwrap();
+    assert_eq!((client.balance, client.locked), (5 * ONE_NEAR, 0));
+    assert_eq!(
+      contract.get_milestone_escrow(escrow_id).unwrap().status,
+      crate::milestone::EscrowStatus::Completed
+    );
+  }
+
+  #[test]
+  fn chats_release_in_parts_with_proportional_fee() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 1.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_sell_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    let half = U128(ONE_NEAR / 2);
+    assert_eq!(contract.release_near_partially(chat_id.clone(), half), "Partially released");
+    // A quarter of the fee, up to f64 rounding that the last part makes up for
+    assert!((contract.revenue as f64 / (ONE_NEAR / 4) as f64 - 1.0).abs() < 1e-9);
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, ONE_NEAR / 2);
+    assert_eq!(
+      contract.release_near_partially(chat_id.clone(), U128(2 * ONE_NEAR)),
+      "Amount must be between zero and the unreleased amount"
+    );
+
+    assert_eq!(contract.release_near_partially(chat_id.clone(), U128(3 * ONE_NEAR / 2)), "Released");
+    let chat = contract.get_chat(chat_id).unwrap();
+    assert!(chat.released);
+    assert_eq!((chat.released_amount, chat.fee_collected), (2 * ONE_NEAR, ONE_NEAR));
+    assert_eq!(contract.revenue, ONE_NEAR);
+    assert_eq!(contract.pub_get_account(alice()).unwrap().balance, 2 * ONE_NEAR);
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().locked, 0);
+  }
+
+  #[test]
+  fn canceling_refunds_the_unreleased_part() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    let chat_id = open_released_sell_chat(&mut contract, false);
+    contract.release_near_partially(chat_id.clone(), U128(ONE_NEAR / 2));
+    assert_eq!(contract.cancel_chat(chat_id), "chat canceled");
+    let acc = contract.pub_get_account(supercode()).unwrap();
+    assert_eq!((acc.balance, acc.locked), (9 * ONE_NEAR / 2, 0));
+  }
+
+  #[test]
+  fn negotiated_rate_locks_escrow_once_accepted() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+
+    let mut alice_context = get_context(false);
+    alice_context.predecessor_account_id = alice();
+    testing_env!(alice_context.clone());
+    let negotiation_id = contract.propose_rate(
+      offer_id,
+      U128(2 * ONE_NEAR),
+      0.9,
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    assert_eq!(
+      contract.accept_rate(negotiation_id.clone()),
+      "Wait for the other side to answer your proposal"
+    );
+
+    testing_env!(get_context(false));
+    assert_eq!(contract.counter_rate(negotiation_id.clone(), U128(2 * ONE_NEAR), 0.95), "Counter proposed");
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().locked, 0);
+
+    testing_env!(alice_context);
+    let chat_id = contract.accept_rate(negotiation_id.clone());
+    assert_eq!(chat_id, "chat-1");
+    let chat = contract.get_chat(chat_id.clone()).unwrap();
+    assert_eq!(chat.rate, 0.95);
+    assert!((chat.fiat_total - 1.9).abs() < 1e-6);
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().locked, 2 * ONE_NEAR);
+    let negotiation = contract.get_negotiation(negotiation_id.clone()).unwrap();
+    assert_eq!(negotiation.status, crate::negotiation::NegotiationStatus::Accepted);
+    assert_eq!(negotiation.chat_id, Some(chat_id));
+    assert_eq!(contract.cancel_negotiation(negotiation_id), "Negotiation is closed");
+  }
+
+  #[test]
+  fn failed_acceptance_keeps_the_negotiation_open() {
+    testing_env!(get_context(false));
+    let mut contract = Contract::new();
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+
+    let mut alice_context = get_context(false);
+    alice_context.predecessor_account_id = alice();
+    testing_env!(alice_context);
+    let negotiation_id = contract.propose_rate(offer_id, U128(ONE_NEAR), 0.9, "M-Pesa".to_string(), "".to_string());
+
+    // alice has nothing deposited to lock
+    testing_env!(get_context(false));
+    assert_eq!(
+      contract.accept_rate(negotiation_id.clone()),
+      "You don't have enough balance to chat with someone"
+    );
+    let negotiation = contract.get_negotiation(negotiation_id).unwrap();
+    assert_eq!(negotiation.status, crate::negotiation::NegotiationStatus::Open);
+    assert_eq!(negotiation.chat_id, None);
+  }
+
+  #[test]
+  fn token_offers_cant_float() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    let mut contract = Contract::new();
+    contract.add_token(usdn(), usdc_metadata());
+    contract.register_new_account(supercode(), None);
+    add_payment_methods(&mut contract);
+
+    testing_env!(get_context(false));
+    let result = contract.add_token_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(1_000_000),
+      U128(4_000_000),
+      1.0,
+      Some(2.0),
+      usdn(),
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    assert_eq!(result, "Floating rates are only supported on near offers");
+    assert!(contract.get_all_token_offers().is_empty());
+  }
+
+  #[test]
+  fn token_offers_cant_be_negotiated() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    testing_env!(context);
+    let mut contract = Contract::new();
+    contract.add_token(usdn(), usdc_metadata());
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    add_payment_methods(&mut contract);
+
+    testing_env!(get_context(false));
+    let offer_id = contract.add_token_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(1_000_000),
+      U128(4_000_000),
+      1.0,
+      None,
+      usdn(),
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+
+    let mut alice_context = get_context(false);
+    alice_context.predecessor_account_id = alice();
+    testing_env!(alice_context);
+    assert_eq!(
+      contract.propose_rate(offer_id, U128(1_000_000), 0.9, "M-Pesa".to_string(), "".to_string()),
+      "Token offers can't be negotiated"
+    );
+    assert!(contract.get_account_negotiations(alice()).is_empty());
+  }
+
+  #[test]
+  fn chats_stored_before_negotiation_still_load() {
+    testing_env!(get_context(false));
+    let chat = crate::upgrade::ChatV2 {
+      id: "chat-1".to_string(),
+      offer_id: "offer-1".to_string(),
+      owner: alice(),
+      offerer: supercode(),
+      amount: 2 * ONE_NEAR,
+      rate: 130.0,
+      trade_cost: 0,
+      trade_cost_usd: 0.0,
+      started_at: 0,
+      ended_at: None,
+      active: true,
+      payer: alice(),
+      receiver: supercode(),
+      paid: false,
+      received: false,
+      canceled: false,
+      released: false,
+      released_amount: ONE_NEAR / 2,
+      fee_collected: 0,
+      payer_has_rated: false,
+      receiver_has_rated: false,
+      payment_method: "M-Pesa".to_string(),
+      payment_msg: "".to_string(),
+      created_on: 0,
+      updated_on: None,
+    };
+    // The user-049 build stored its chats as the variant now at index 1
+    let mut bytes = vec![1u8];
+    bytes.extend(near_sdk::borsh::BorshSerialize::try_to_vec(&chat).unwrap());
+    let stored: VersionedChat = near_sdk::borsh::BorshDeserialize::try_from_slice(&bytes).unwrap();
+    let chat = Chat::from(stored);
+    assert_eq!(chat.released_amount, ONE_NEAR / 2);
+    assert_eq!(chat.fiat_total, 260.0);
+  }
+
+  #[test]
+  fn payers_release_buy_chats_in_parts() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 1.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&alice());
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_buy_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+
+    // The payer locked the escrow and fee, so the payer releases them
+    let mut context = get_context(false);
+    context.predecessor_account_id = alice();
+    testing_env!(context);
+    assert_eq!(contract.release_near_partially(chat_id.clone(), U128(ONE_NEAR)), "Partially released");
+    assert_eq!(contract.release_near_partially(chat_id.clone(), U128(ONE_NEAR)), "Released");
+    let payer = contract.pub_get_account(alice()).unwrap();
+    assert_eq!((payer.balance, payer.locked), (2 * ONE_NEAR, 0));
+    assert_eq!(contract.pub_get_account(supercode()).unwrap().balance, 2 * ONE_NEAR);
+    assert_eq!(contract.revenue, ONE_NEAR);
+  }
+
+  #[test]
+  #[should_panic(expected = "Only the account holding the escrow can release it")]
+  fn receivers_cant_release_buy_chats_in_parts() {
+    let mut context = get_context(false);
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&alice());
+    add_payment_methods(&mut contract);
+    let offer_id = contract.add_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(ONE_NEAR),
+      U128(2 * ONE_NEAR),
+      1.0,
+      None,
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_buy_chat(
+      None,
+      offer_id,
+      alice(),
+      U128(2 * ONE_NEAR),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+
+    // supercode is the receiver, alice's escrow is not theirs to release
+    contract.release_near_partially(chat_id, U128(ONE_NEAR));
+  }
+
+  #[test]
+  #[should_panic(expected = "Only the account holding the escrow can release it")]
+  fn receivers_cant_release_token_buy_chats_in_parts() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 1.0;
+    contract.add_token(usdn(), usdc_metadata());
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&alice());
+    contract.get_account(alice()).unwrap().deposit_tokens(usdn(), 4_000_000);
+    add_payment_methods(&mut contract);
+
+    testing_env!(get_context(false));
+    let offer_id = contract.add_token_offer(
+      None,
+      "buy".to_string(),
+      supercode(),
+      U128(1_000_000),
+      U128(4_000_000),
+      1.0,
+      None,
+      usdn(),
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_token_buy_chat(
+      None,
+      offer_id,
+      usdn(),
+      alice(),
+      U128(4_000_000),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+
+    // supercode is the receiver, the tokens are alice's escrow
+    contract.release_tokens_partially(chat_id, U128(1_000_000));
+  }
+
+  #[test]
+  #[should_panic(expected = "Only the account holding the escrow can release it")]
+  fn strangers_cant_release_token_chats() {
+    let mut context = get_context(false);
+    context.predecessor_account_id = master();
+    context.attached_deposit = ONE_NEAR * 5;
+    testing_env!(context);
+
+    let mut contract = Contract::new();
+    contract.send_cost = 0.0;
+    contract.add_token(usdn(), usdc_metadata());
+    contract.register_new_account(supercode(), None);
+    contract.register_new_account(alice(), None);
+    contract.contract_deposit(&supercode());
+    contract.get_account(supercode()).unwrap().deposit_tokens(usdn(), 4_000_000);
+    add_payment_methods(&mut contract);
+
+    testing_env!(get_context(false));
+    let offer_id = contract.add_token_offer(
+      None,
+      "sell".to_string(),
+      supercode(),
+      U128(1_000_000),
+      U128(4_000_000),
+      1.0,
+      None,
+      usdn(),
+      mpesa(),
+      "KES".to_string(),
+      "".to_string(),
+      None,
+    );
+    let chat_id = contract.add_token_sell_chat(
+      None,
+      offer_id,
+      usdn(),
+      alice(),
+      U128(4_000_000),
+      alice(),
+      supercode(),
+      "M-Pesa".to_string(),
+      "".to_string(),
+    );
+    assert!(contract.get_token_chat(chat_id.clone()).is_some());
+
+    // Neither party, and no fiat was marked paid
+    let mut context = get_context(false);
+    context.predecessor_account_id = "mallory.testnet".parse().unwrap();
+    testing_env!(context);
+    contract.release_tokens(chat_id);
+  }
 }
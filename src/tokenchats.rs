@@ -8,7 +8,9 @@ use near_sdk::{
 };
 
 
+use crate::chat::escrow_sides;
 use crate::tokenoffers::CompleteTokenOffer;
+use crate::ids::TOKEN_CHAT_ID_PREFIX;
 use crate::*;
 
 // #[near_bindgen]
@@ -32,8 +34,11 @@ pub struct TokenChat {
   pub received: bool,
   pub canceled: bool,
   pub released: bool,
+  pub released_amount: Balance, // Escrow released so far, partial releases add up to amount
+  pub fee_collected: Balance,   // Part of trade_cost taken with the partial releases
   pub payer_has_rated: bool,
   pub receiver_has_rated: bool,
+  pub payment_method: String, // Payment method picked from the offer
   pub payment_msg: String,
   pub created_on: Timestamp,
   pub updated_on: Option<Timestamp>,
@@ -59,6 +64,7 @@ impl TokenChat {
     trade_cost_usd: f64,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
   ) -> Self {
     Self {
@@ -79,8 +85,11 @@ impl TokenChat {
       received: false,
       canceled: false,
       released: false,
+      released_amount: 0,
+      fee_collected: 0,
       payer_has_rated: false,
       receiver_has_rated: false,
+      payment_method,
       payment_msg,
       created_on: env::block_timestamp(),
       updated_on: Some(env::block_timestamp()),
@@ -115,6 +124,8 @@ impl TokenChat {
 
   pub fn mark_as_released(&mut self) {
     self.released = true;
+    self.released_amount = self.amount;
+    self.fee_collected = self.trade_cost;
   }
 
   pub fn mark_as_canceled(&mut self) -> String {
@@ -128,49 +139,82 @@ impl TokenChat {
   }
 }
 
-#[near_bindgen]
 impl Contract {
-  pub fn add_token_buy_chat(
+  pub(crate) fn index_token_offer_chat(&mut self, offer_id: &String, chat_id: &String) {
+    let mut chat_ids = self.token_offer_chats.get(offer_id).unwrap_or_else(|| {
+      UnorderedSet::new(StorageKey::TokenOfferChatIds {
+        offer_hash: env::sha256_array(offer_id.as_bytes()),
+      })
+    });
+    chat_ids.insert(chat_id);
+    self.token_offer_chats.insert(offer_id, &chat_ids);
+  }
+
+  // Opens a chat on a token buy offer and locks the tokens and fee of the payer,
+  // the error is why it couldn't be opened
+  pub(crate) fn open_token_buy_chat(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_id: String,
     token_id: AccountId,
     owner: AccountId,
     amount: U128,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
-    trade_cost: U128,
-    trade_cost_usd: f64
-  ) -> String {
+  ) -> Result<String, String> {
+    self.assert_not_paused(Subsystem::Chats);
+    // The fee is send_cost after the volume discount of the account locking it
+    let (fee, trade_cost_usd) = self.trade_fee(&payer, token_id.as_str());
+    let trade_cost = U128(fee);
     let offer = self.get_token_offer(offer_id.clone());
 
     if owner.clone() == offer.as_ref().unwrap().offerer.clone() {
       // panic!("You can't chat with yourself");
-      return "You can't chat with yourself".to_string();
+      return Err("You can't chat with yourself".to_string());
+    }
+    if self.is_blocked_between(owner.clone(), offer.as_ref().unwrap().offerer.clone()) {
+      return Err("You can't open a chat with this account".to_string());
+    }
+    if let Some(err) = self.check_offer_requirements(&offer.as_ref().unwrap().requirements, owner.clone()) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_denylisted(&[&payer, &receiver]) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_trade_limits(token_id.as_str(), amount.0, [&payer, &receiver]) {
+      return Err(err);
     }
     if offer.as_ref().is_none() {
-      return "Offer not found".to_string();
+      return Err("Offer not found".to_string());
     } else {
       if offer.as_ref().unwrap().offer_type.clone() == "buy".to_string() {
-        let chat_initiator = self.get_account(payer.clone());
+        match offer.as_ref().unwrap().get_payment(&payment_method) {
+          None => return Err("Payment method is not accepted by this offer".to_string()),
+          Some(payment) if !payment.accepts(u128::from(amount)) => {
+            return Err("Amount is outside the limits of the payment method".to_string())
+          }
+          _ => {}
+        }
+        let chat_initiator = self.pub_get_account(payer.clone());
         if chat_initiator.as_ref().is_none() {
-          return "You must be a registered user to chat with someone".to_string();
-        } else if chat_initiator.as_ref().unwrap().clone().balance < u128::from(trade_cost.clone()) {
-          return "You don't have enough balance to chat with someone".to_string();
+          return Err("You must be a registered user to chat with someone".to_string());
+        } else if chat_initiator.as_ref().unwrap().balance < u128::from(trade_cost.clone()) {
+          return Err("You don't have enough balance to chat with someone".to_string());
         } else {
           if chat_initiator
             .as_ref()
             .unwrap()
-            .clone()
             .get_token_balance(token_id.clone())
             < u128::from(amount.clone())
           {
-            return "You don't have enough balance to chat with someone".to_string();
+            return Err("You don't have enough balance to chat with someone".to_string());
           } else {
+            let id = self.resolve_id(TOKEN_CHAT_ID_PREFIX, id, |c, id| c.tokenchats.get(id).is_some())?;
             self.tokenchats.insert(
               &id.clone(),
-              &TokenChat::new(
+              &VersionedTokenChat::from(TokenChat::new(
                 id.clone(),
                 offer_id.clone(),
                 token_id.clone(),
@@ -181,8 +225,9 @@ impl Contract {
                 trade_cost_usd.clone(),
                 payer.clone(),
                 receiver.clone(),
+                payment_method,
                 payment_msg,
-              ),
+              )),
             );
             self
               .get_account(payer.clone())
@@ -192,52 +237,80 @@ impl Contract {
               .get_account(payer.clone())
               .unwrap()
               .lock(u128::from(trade_cost.clone()));
-            return "created".to_string();
+            self.record_chat_opened(&payer, &receiver);
+            self.index_token_offer_chat(&offer_id, &id);
+            self.record_trade_usage(token_id.as_str(), amount.0, [&payer, &receiver]);
+            return Ok(id);
           }
         }
       }
-      return "Offer is not for buy".to_string();
+      return Err("Offer is not for buy".to_string());
     }
   }
- 
-  pub fn add_token_sell_chat(
+
+  // Opens a chat on a token sell offer and locks the tokens and fee of the offerer,
+  // the error is why it couldn't be opened
+  pub(crate) fn open_token_sell_chat(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_id: String,
     token_id: AccountId,
     owner: AccountId,
     amount: U128,
     payer: AccountId,
     receiver: AccountId,
+    payment_method: String,
     payment_msg: String,
-    trade_cost: U128,
-    trade_cost_usd: f64
-  ) -> String {
+  ) -> Result<String, String> {
+    self.assert_not_paused(Subsystem::Chats);
+    // The fee is send_cost after the volume discount of the account locking it
+    let (fee, trade_cost_usd) = self.trade_fee(&receiver, token_id.as_str());
+    let trade_cost = U128(fee);
     let offer = self.get_token_offer(offer_id.clone());
 
     if owner.clone() == offer.as_ref().unwrap().offerer.clone() {
-      return "You can't chat with yourself".to_string();
+      return Err("You can't chat with yourself".to_string());
+    }
+    if self.is_blocked_between(owner.clone(), offer.as_ref().unwrap().offerer.clone()) {
+      return Err("You can't open a chat with this account".to_string());
+    }
+    if let Some(err) = self.check_offer_requirements(&offer.as_ref().unwrap().requirements, owner.clone()) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_denylisted(&[&payer, &receiver]) {
+      return Err(err);
+    }
+    if let Some(err) = self.check_trade_limits(token_id.as_str(), amount.0, [&payer, &receiver]) {
+      return Err(err);
     }
     if offer.as_ref().is_none() {
-      return "Offer not found".to_string();
+      return Err("Offer not found".to_string());
     } else {
       if offer.as_ref().unwrap().offer_type.clone() == "sell".to_string() {
-        let offer_owner = self.get_account(receiver.clone());
+        match offer.as_ref().unwrap().get_payment(&payment_method) {
+          None => return Err("Payment method is not accepted by this offer".to_string()),
+          Some(payment) if !payment.accepts(u128::from(amount)) => {
+            return Err("Amount is outside the limits of the payment method".to_string())
+          }
+          _ => {}
+        }
+        let offer_owner = self.pub_get_account(receiver.clone());
         if offer_owner
           .as_ref()
           .unwrap()
           .get_token_balance(token_id.clone())
           < u128::from(amount.clone())
         {
-          return "Offerer does not have sufficient balance to hold the trade.".to_string();
+          return Err("Offerer does not have sufficient balance to hold the trade.".to_string());
         } else if offer_owner.as_ref().unwrap().balance < u128::from(trade_cost.clone()) {
-          return "Offerer does not have sufficient balance to hold the trade. ".to_string();
+          return Err("Offerer does not have sufficient balance to hold the trade. ".to_string());
         } else {
+          let id = self.resolve_id(TOKEN_CHAT_ID_PREFIX, id, |c, id| c.tokenchats.get(id).is_some())?;
           self.tokenchats.insert(
             &id.clone(),
-            &TokenChat::new(
-              id,
-              offer_id,
+            &VersionedTokenChat::from(TokenChat::new(
+              id.clone(),
+              offer_id.clone(),
               token_id.clone(),
               owner,
               offer.as_ref().unwrap().offerer.clone(),
@@ -246,8 +319,9 @@ impl Contract {
               trade_cost_usd.clone(),
               payer.clone(),
               receiver.clone(),
+              payment_method,
               payment_msg,
-            ),
+            )),
           );
           self
             .get_account(receiver.clone())
@@ -257,46 +331,92 @@ impl Contract {
             .get_account(receiver.clone())
             .unwrap()
             .lock(u128::from(trade_cost.clone()));
-          return "created".to_string();
+          self.record_chat_opened(&payer, &receiver);
+          self.index_token_offer_chat(&offer_id, &id);
+          self.record_trade_usage(token_id.as_str(), amount.0, [&payer, &receiver]);
+          return Ok(id);
         }
       }
-      return "Offer is not for sell".to_string();
+      return Err("Offer is not for sell".to_string());
+    }
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  pub fn add_token_buy_chat(
+    &mut self,
+    id: Option<String>,
+    offer_id: String,
+    token_id: AccountId,
+    owner: AccountId,
+    amount: U128,
+    payer: AccountId,
+    receiver: AccountId,
+    payment_method: String,
+    payment_msg: String,
+  ) -> String {
+    match self.open_token_buy_chat(id, offer_id, token_id, owner, amount, payer, receiver, payment_method, payment_msg) {
+      Ok(id) => id,
+      Err(err) => err,
+    }
+  }
+ 
+  pub fn add_token_sell_chat(
+    &mut self,
+    id: Option<String>,
+    offer_id: String,
+    token_id: AccountId,
+    owner: AccountId,
+    amount: U128,
+    payer: AccountId,
+    receiver: AccountId,
+    payment_method: String,
+    payment_msg: String,
+  ) -> String {
+    match self.open_token_sell_chat(id, offer_id, token_id, owner, amount, payer, receiver, payment_method, payment_msg) {
+      Ok(id) => id,
+      Err(err) => err,
     }
   }
 
   pub fn get_token_chat(&mut self, chat_id: String) -> Option<TokenChat> {
-    self.tokenchats.get(&chat_id)
+    self.tokenchats.get(&chat_id).map(TokenChat::from)
   }
 
   pub fn pub_get_token_chat(&mut self, chat_id: String) -> CompleteTokenChat {
-    let chat = self.tokenchats.get(&chat_id);
+    let chat = self.tokenchats.get(&chat_id).map(TokenChat::from);
     let offer = self.pub_get_token_offer(chat.as_ref().unwrap().offer_id.clone());
     CompleteTokenChat { chat, offer }
   }
 
   pub fn mark_token_as_paid(&mut self, chat_id: String) -> String {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.tokenchats.remove(&chat_id.clone()).map(TokenChat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
 
     if chat.clone().active {
       chat.mark_as_paid();
-      self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       return "success".to_string();
     } else {
-      self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
 
   pub fn mark_token_as_received(&mut self, chat_id: String) -> String {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.tokenchats.remove(&chat_id.clone()).map(TokenChat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
 
     if chat.clone().active {
       chat.mark_as_received();
-      self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       self.release_tokens(chat_id.clone());
       return "success".to_string();
     } else {
-      self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
@@ -314,6 +434,7 @@ impl Contract {
       .to_vec()
       .into_iter()
       .for_each(|(_id, chat)| {
+        let chat = TokenChat::from(chat);
         if chat.owner == account_id || chat.offerer == account_id {
           tokenchats.push(chat)
         }
@@ -322,11 +443,14 @@ impl Contract {
   }
 
   pub fn clear_token_chats(&mut self) {
+    self.assert_role(Role::Admin);
     self.tokenchats.clear();
   }
 
   pub fn cancel_token_chat(&mut self, chat_id: String) -> String {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
+    let mut chat = self.tokenchats.remove(&chat_id.clone()).map(TokenChat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
+    self.assert_can_cancel(&chat.payer, &chat.receiver);
     let offer = self.get_token_offer(chat.clone().offer_id).unwrap();
 
     if chat.clone().active {
@@ -334,117 +458,138 @@ impl Contract {
         self
           .get_account(chat.clone().owner)
           .unwrap()
-          .unlock_tokens(chat.clone().token_id, u128::from(chat.clone().amount));
+          .unlock_tokens(chat.clone().token_id, chat.amount - chat.released_amount);
       } else {
         self
           .get_account(offer.offerer.clone())
           .unwrap()
-          .unlock_tokens(chat.clone().token_id, u128::from(chat.clone().amount));
+          .unlock_tokens(chat.clone().token_id, chat.amount - chat.released_amount);
       }
       chat.mark_as_canceled();
-      self.tokenchats.insert(&chat_id.clone(), &chat);
+      if chat.canceled {
+        self.record_chat_canceled(&chat.payer, &chat.receiver);
+      }
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat));
       return "chat canceled".to_string();
     } else {
-      self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+      self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
       return "chat is not active".to_string();
     }
   }
 
   pub fn release_tokens(&mut self, chat_id: String) {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
+    self.assert_not_emergency();
+    let mut chat = self.tokenchats.remove(&chat_id.clone()).map(TokenChat::from).unwrap();
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
     let offer = self.get_token_offer(chat.offer_id.clone()).unwrap();
+    self.assert_escrow_holder(&escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver).0);
+    // Partial releases already moved part of the escrow and fee
+    let remaining = chat.amount - chat.released_amount;
+    let fee = chat.trade_cost - chat.fee_collected;
+    let fee_usd = chat.trade_cost_usd * fee as f64 / chat.trade_cost.max(1) as f64;
 
     if offer.offer_type.clone() == "buy".to_string() {
       if chat.clone().released {
-        self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+        self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
         panic!("Amount already released");
       } else {
         chat.mark_as_released();
-        self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+        self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
+        self.record_chat_released(&chat.payer, &chat.receiver, &chat.payer, chat.started_at);
+        self.record_released_volume(chat.token_id.as_str(), remaining, [&chat.payer, &chat.receiver]);
         // self.trades.push(&trade);
-        self.create_revenue(
+        self.record_revenue(
           chat.clone().token_id.clone().to_string(),
           "trade".to_string(),
           chat.clone().payer.clone(),
-          chat.clone().trade_cost.clone(),
-          chat.clone().trade_cost_usd.clone(),
+          fee,
+          fee_usd,
         );
         self.send_tokens(
           chat.clone().payer.clone(),
           chat.clone().receiver.clone(),
           offer.token,
-          U128::from(chat.clone().amount),
+          U128(remaining),
           chat_id.clone(),
         );
       }
     } else {
       if chat.clone().released {
-        self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+        self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
         panic!("Amount already released");
       } else {
         chat.mark_as_released();
         chat.update_ended_at();
-        self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+        self.tokenchats.insert(&chat_id.clone(), &VersionedTokenChat::from(chat.clone()));
+        self.record_chat_released(&chat.payer, &chat.receiver, &chat.receiver, chat.started_at);
+        self.record_released_volume(chat.token_id.as_str(), remaining, [&chat.payer, &chat.receiver]);
         // self.trades.push(&trade);
-        self.create_revenue(
+        self.record_revenue(
           chat.clone().token_id.clone().to_string(),
           "trade".to_string(),
           chat.clone().receiver.clone(),
-          chat.clone().trade_cost.clone(),
-          chat.clone().trade_cost_usd.clone(),
+          fee,
+          fee_usd,
         );
         self.send_tokens(
           chat.clone().receiver.clone(),
           chat.clone().payer.clone(),
           offer.token,
-          U128::from(chat.clone().amount),
+          U128(remaining),
           chat_id.clone(),
         );
       }
     }
   }
 
-  pub fn receiver_token_rate_chat(&mut self, chat_id: String, rating: bool) {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
-    let account_id = env::predecessor_account_id();
-    if account_id.clone() == chat.clone().receiver {
-      if chat.clone().receiver_has_rated {
-        if rating.clone() {
-          self
-            .get_account(chat.clone().payer.clone())
-            .unwrap()
-            .add_like();
-        } else {
-          self
-            .get_account(chat.clone().payer.clone())
-            .unwrap()
-            .add_dislike();
-        }
-      }
+  /// Lets the account holding the escrow release part of the tokens, see
+  /// release_near_partially. Parts are credited to the internal balance.
+  pub fn release_tokens_partially(&mut self, chat_id: String, amount: U128) -> String {
+    self.assert_not_emergency();
+    let mut chat = match self.tokenchats.get(&chat_id).map(TokenChat::from) {
+      Some(chat) => chat,
+      None => return "Chat not found".to_string(),
+    };
+    self.assert_escrow_not_frozen(&chat.payer, &chat.receiver);
+    let offer = self.get_token_offer(chat.offer_id.clone()).unwrap();
+    let (locker, recipient) = escrow_sides(&offer.offer_type, &chat.payer, &chat.receiver);
+    self.assert_escrow_holder(&locker);
+    if !chat.active || chat.released || chat.canceled {
+      return "chat is not active".to_string();
     }
-    chat.receiver_has_rated = true;
-    self.tokenchats.insert(&chat_id.clone(), &chat);
-  }
+    let remaining = chat.amount - chat.released_amount;
+    if amount.0 == 0 || amount.0 > remaining {
+      return "Amount must be between zero and the unreleased amount".to_string();
+    }
+    let fee = if amount.0 == remaining {
+      chat.trade_cost - chat.fee_collected
+    } else {
+      proportional_fee(chat.trade_cost, amount.0, chat.amount)
+    };
+    let fee_usd = chat.trade_cost_usd * fee as f64 / chat.trade_cost.max(1) as f64;
 
-  pub fn payer_token_rate_chat(&mut self, chat_id: String, rating: bool) {
-    let mut chat = self.tokenchats.remove(&chat_id.clone()).unwrap();
-    let account_id = env::predecessor_account_id();
-    if account_id.clone() == chat.clone().payer {
-      if chat.clone().payer_has_rated {
-        if rating.clone() {
-          self
-            .get_account(chat.clone().receiver.clone())
-            .unwrap()
-            .add_like();
-        } else {
-          self
-            .get_account(chat.clone().receiver.clone())
-            .unwrap()
-            .add_dislike();
-        }
-      }
+    self.get_account(locker.clone()).unwrap().unlock_tokens(chat.token_id.clone(), amount.0);
+    self.move_balance(&locker, &recipient, chat.token_id.as_str(), amount.0);
+    self.record_revenue(chat.token_id.to_string(), "trade".to_string(), locker.clone(), fee, fee_usd);
+    self.record_released_volume(chat.token_id.as_str(), amount.0, [&chat.payer, &chat.receiver]);
+    chat.released_amount += amount.0;
+    chat.fee_collected += fee;
+    if chat.released_amount < chat.amount {
+      self.tokenchats.insert(&chat_id, &VersionedTokenChat::from(chat));
+      return "Partially released".to_string();
     }
-    chat.payer_has_rated = true;
-    self.tokenchats.insert(&chat_id.clone(), &chat.clone());
+    chat.received = true;
+    chat.mark_as_released();
+    chat.update_ended_at();
+    self.tokenchats.insert(&chat_id, &VersionedTokenChat::from(chat.clone()));
+    self.record_chat_released(&chat.payer, &chat.receiver, &locker, chat.started_at);
+    "Released".to_string()
+  }
+
+  pub fn get_token_offer_chats(&self, offer_id: String) -> Vec<TokenChat> {
+    self
+      .token_offer_chats
+      .get(&offer_id)
+      .map_or(Vec::new(), |chat_ids| chat_ids.iter().filter_map(|chat_id| self.tokenchats.get(&chat_id).map(TokenChat::from)).collect())
   }
 }
@@ -5,6 +5,8 @@ use near_sdk::{
   AccountId, Timestamp,
 };
 
+use crate::ids::TOKEN_OFFER_ID_PREFIX;
+use crate::offer::OfferRequirements;
 use crate::{account::PubAccountInfo, *};
 
 // #[near_bindgen]
@@ -19,11 +21,12 @@ pub struct TokenOffer {
   pub offer_rate: f32,
   pub active: bool,
   pub token: AccountId,
-  pub payment: String,
+  pub payments: Vec<OfferPayment>,
   pub currency: String,
   pub instructions: String,
   pub created_on: Timestamp,
   pub updated_on: Option<Timestamp>,
+  pub requirements: OfferRequirements,
 }
 
 #[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
@@ -37,11 +40,12 @@ pub struct CompleteTokenOffer {
   pub offer_rate: f32,
   pub active: bool,
   pub token: Option<TokenMetadata>,
-  pub payment: Option<PaymentMethod>,
+  pub payments: Vec<CompleteOfferPayment>,
   pub currency: String,
   pub instructions: String,
   pub created_on: Timestamp,
   pub updated_on: Option<Timestamp>,
+  pub requirements: OfferRequirements,
 }
 
 // #[near_bindgen]
@@ -54,7 +58,7 @@ impl TokenOffer {
     max_amount: U128,
     offer_rate: f32,
     token: AccountId,
-    payment: String,
+    payments: Vec<OfferPayment>,
     currency: String,
     instructions: String,
   ) -> Self {
@@ -67,11 +71,12 @@ impl TokenOffer {
       offer_rate,
       active: true,
       token,
-      payment,
+      payments,
       currency,
       instructions,
       created_on: env::block_timestamp(),
       updated_on: None,
+      requirements: OfferRequirements::default(),
     }
   }
 
@@ -82,7 +87,11 @@ impl TokenOffer {
   pub fn update_offer_status(&mut self, active: bool) {
     self.active = active;
   }
-  pub fn make_complete_offer(&self, payment: Option<PaymentMethod>) -> CompleteTokenOffer {
+
+  pub fn get_payment(&self, method: &str) -> Option<&OfferPayment> {
+    self.payments.iter().find(|payment| payment.method == method)
+  }
+  pub fn make_complete_offer(&self, payments: Vec<CompleteOfferPayment>) -> CompleteTokenOffer {
     return CompleteTokenOffer {
       id: self.id.clone(),
       offer_type: self.offer_type.clone(),
@@ -91,12 +100,13 @@ impl TokenOffer {
       max_amount: self.max_amount,
       offer_rate: self.offer_rate,
       active: self.active,
-      payment,
+      payments,
       currency: self.currency.clone(),
       instructions: self.instructions.clone(),
       token: None,
       created_on: self.created_on,
       updated_on: self.updated_on,
+      requirements: self.requirements.clone(),
     };
   }
 }
@@ -105,20 +115,37 @@ impl TokenOffer {
 impl Contract {
   pub fn add_token_offer(
     &mut self,
-    id: String,
+    id: Option<String>,
     offer_type: String,
     offerer: AccountId,
     min_amount: U128,
     max_amount: U128,
     offer_rate: f32,
+    margin: Option<f32>,
     token: AccountId,
-    payment: String,
+    payments: Vec<OfferPayment>,
     currency: String,
     instructions: String,
+    requirements: Option<OfferRequirements>,
   ) -> String {
+    self.assert_not_paused(Subsystem::Offers);
+    // The oracle only quotes near, token offers keep a static rate
+    if margin.is_some() {
+      return "Floating rates are only supported on near offers".to_string();
+    }
+    if let Some(err) = self.check_denylisted(&[&offerer]) {
+      return err;
+    }
+    if let Some(err) = self.validate_offer_payments(&payments) {
+      return err;
+    }
+    let id = match self.resolve_id(TOKEN_OFFER_ID_PREFIX, id, |c, id| c.tokenoffers.get(id).is_some()) {
+      Ok(id) => id,
+      Err(err) => return err,
+    };
     // Check offer type, if buy, don't check account balance
-    let account = self.get_account(offerer.clone()).unwrap();
-    let offer = TokenOffer::new(
+    let account = self.pub_get_account(offerer.clone()).unwrap();
+    let mut offer = TokenOffer::new(
       id.clone(),
       offer_type.clone(),
       offerer,
@@ -126,18 +153,21 @@ impl Contract {
       max_amount,
       offer_rate,
       token.clone(),
-      payment,
+      payments,
       currency,
       instructions,
     );
+    offer.requirements = requirements.unwrap_or_default();
 
     if offer_type.clone() == "buy".to_string() {
-      self.tokenoffers.insert(&id.clone(), &offer);
-      return "Offer created successfully".to_string();
+      self.index_offer(token.as_str(), &offer.currency, &id);
+      self.tokenoffers.insert(&id.clone(), &VersionedTokenOffer::from(offer));
+      return id;
     } else {
       if account.get_token_balance(token.clone()) >= offer.max_amount.clone() {
-        self.tokenoffers.insert(&id.clone(), &offer);
-        return "Offer created successfully".to_string();
+        self.index_offer(token.as_str(), &offer.currency, &id);
+        self.tokenoffers.insert(&id.clone(), &VersionedTokenOffer::from(offer));
+        return id;
       } else {
         // panic!("You do not have enough balance to add offer");
         return "You do not have enough balance to add offer".to_string();
@@ -147,11 +177,7 @@ impl Contract {
 
   pub fn get_account_token_offers(&self, account_id: AccountId) -> Vec<TokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| {
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| {
         if offer.offerer == account_id {
           offers.push(offer)
         }
@@ -165,14 +191,10 @@ impl Contract {
 
   pub fn get_buy_token_offers(&self) -> Vec<CompleteTokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| {
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| {
         if offer.offer_type == "buy".to_string() {
-          let payment = self.get_payment(offer.payment.clone());
-          let mut comp_offer = offer.make_complete_offer(payment);
+          let payments = self.complete_offer_payments(&offer.payments);
+          let mut comp_offer = offer.make_complete_offer(payments);
           let offerer = self.acc_pub_info(offer.offerer);
           let token_meta = self.get_token(offer.token);
           comp_offer.token = token_meta;
@@ -185,14 +207,10 @@ impl Contract {
 
   pub fn get_buy_token_offers_by_token(&self, token: AccountId) -> Vec<CompleteTokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| {
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| {
         if offer.offer_type == "buy".to_string() && offer.token == token {
-          let payment = self.get_payment(offer.payment.clone());
-          let mut comp_offer = offer.make_complete_offer(payment);
+          let payments = self.complete_offer_payments(&offer.payments);
+          let mut comp_offer = offer.make_complete_offer(payments);
           let offerer = self.acc_pub_info(offer.offerer);
           let token_meta = self.get_token(offer.token);
           comp_offer.token = token_meta;
@@ -205,14 +223,14 @@ impl Contract {
 
   // Used internally
   pub fn get_token_offer(&self, offer_id: String) -> Option<TokenOffer> {
-    self.tokenoffers.get(&offer_id)
+    self.tokenoffers.get(&offer_id).map(TokenOffer::from)
   }
 
   // Used externally from the frontend
   pub fn pub_get_token_offer(&self, offer_id: String) -> Option<CompleteTokenOffer> {
-    let offer = self.tokenoffers.get(&offer_id).unwrap();
-    let payment = self.get_payment(offer.payment.clone());
-    let mut comp_offer = offer.make_complete_offer(payment);
+    let offer = self.tokenoffers.get(&offer_id).map(TokenOffer::from).unwrap();
+    let payments = self.complete_offer_payments(&offer.payments);
+    let mut comp_offer = offer.make_complete_offer(payments);
     let offerer = self.acc_pub_info(offer.offerer);
     let token_meta = self.get_token(offer.token);
     comp_offer.token = token_meta;
@@ -222,14 +240,10 @@ impl Contract {
 
   pub fn get_sell_token_offers(&self) -> Vec<CompleteTokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| {
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| {
         if offer.offer_type == "sell".to_string() {
-          let payment = self.get_payment(offer.payment.clone());
-          let mut comp_offer = offer.make_complete_offer(payment);
+          let payments = self.complete_offer_payments(&offer.payments);
+          let mut comp_offer = offer.make_complete_offer(payments);
           let offerer = self.acc_pub_info(offer.offerer);
           let token_meta = self.get_token(offer.token);
           comp_offer.token = token_meta;
@@ -242,14 +256,10 @@ impl Contract {
 
   pub fn get_sell_token_offers_by_token(&self, token: AccountId) -> Vec<CompleteTokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| {
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| {
         if offer.offer_type == "sell".to_string() && offer.token == token {
-          let payment = self.get_payment(offer.payment.clone());
-          let mut comp_offer = offer.make_complete_offer(payment);
+          let payments = self.complete_offer_payments(&offer.payments);
+          let mut comp_offer = offer.make_complete_offer(payments);
           let offerer = self.acc_pub_info(offer.offerer);
           let token_meta = self.get_token(offer.token);
           comp_offer.token = token_meta;
@@ -262,21 +272,22 @@ impl Contract {
 
   pub fn get_all_token_offers(&self) -> Vec<TokenOffer> {
     let mut offers = Vec::new();
-    self
-      .tokenoffers
-      .to_vec()
-      .into_iter()
-      .for_each(|(_id, offer)| offers.push(offer));
+    self.tokenoffers.values().map(TokenOffer::from).for_each(|offer| offers.push(offer));
     offers
   }
 
   pub fn update_token_offer_status(&mut self, offer_id: String, active: bool) {
-    let mut offer = self.tokenoffers.remove(&offer_id.clone()).unwrap();
+    let mut offer = self.tokenoffers.remove(&offer_id.clone()).map(TokenOffer::from).unwrap();
+    if active {
+      self.assert_not_denylisted(&offer.offerer);
+    }
     offer.update_offer_status(active);
-    self.tokenoffers.insert(&offer_id, &offer);
+    self.tokenoffers.insert(&offer_id, &VersionedTokenOffer::from(offer));
   }
 
   pub fn clear_token_offers(&mut self) {
+    self.assert_role(Role::Admin);
     self.tokenoffers.clear();
+    self.clear_offer_book(false);
   }
 }
@@ -5,6 +5,7 @@ use near_sdk::{
   AccountId, Timestamp,
 };
 
+use crate::ids::TRADE_ID_PREFIX;
 use crate::*;
 
 // #[near_bindgen]
@@ -65,7 +66,7 @@ impl Trade {
 impl Contract {
   pub fn add_trade(
     &mut self,
-    id: String,
+    id: Option<String>,
     trade_type: String,
     seller: AccountId,
     buyer: AccountId,
@@ -74,9 +75,12 @@ impl Contract {
     token_id: String,
     start_timestamp: Option<Timestamp>,
     end_timestamp: Option<Timestamp>,
-  ) {
+  ) -> String {
+    let id = self
+      .resolve_id(TRADE_ID_PREFIX, id, |c, id| c.get_trade(id.clone()).is_some())
+      .unwrap_or_else(|err| env::panic_str(&err));
     let trade = Trade::new(
-      id,
+      id.clone(),
       trade_type,
       seller,
       buyer,
@@ -87,6 +91,7 @@ impl Contract {
       end_timestamp,
     );
     self.trades.push(&trade);
+    id
   }
 
   pub fn get_trade(&self, id: String) -> Option<Trade> {
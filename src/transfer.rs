@@ -2,11 +2,16 @@ use near_sdk::{
   borsh::{self, BorshDeserialize, BorshSerialize},
   env,
   serde::{Deserialize, Serialize},
-  AccountId, Timestamp,
+  json_types::U128,
+  AccountId, Balance, Timestamp, ONE_NEAR,
 };
 
+use crate::ids::TRANSFER_ID_PREFIX;
+use crate::offerbook::NEAR_ASSET;
 use crate::*;
 
+pub const MAX_BATCH_RECIPIENTS: usize = 50;
+
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Debug, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Transfer {
@@ -29,11 +34,109 @@ impl Transfer {
   }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BatchRecipient {
+  pub account_id: AccountId,
+  pub amount: U128,
+}
+
+impl Contract {
+  // Flat fee in yoctoNear charged per chat and per batch, send_cost is quoted
+  // in Near and rounded to micro Near so 0.05 isn't off by the f32 error
+  pub(crate) fn send_fee(&self) -> Balance {
+    (self.send_cost as f64 * 1_000_000.0).round() as Balance * (ONE_NEAR / 1_000_000)
+  }
+
+  // Free balance of near (asset "near") or a token
+  pub(crate) fn free_balance(&self, account_id: &AccountId, asset: &str) -> Balance {
+    let acc = match self.pub_get_account(account_id.clone()) {
+      Some(acc) => acc,
+      None => return 0,
+    };
+    if asset == NEAR_ASSET {
+      acc.balance
+    } else {
+      asset.parse().ok().and_then(|token| acc.tokens.get(&token)).unwrap_or(0)
+    }
+  }
+
+  // Moves free balance between registered accounts and records the transfer
+  pub(crate) fn move_balance(&mut self, from: &AccountId, to: &AccountId, asset: &str, amount: Balance) -> String {
+    if asset == NEAR_ASSET {
+      self.get_account(from.clone()).expect("Account not found").balance -= amount;
+      self.get_account(to.clone()).expect("Account not found").balance += amount;
+    } else {
+      let token: AccountId = asset.parse().unwrap();
+      self.get_account(from.clone()).expect("Account not found").withdraw_tokens(token.clone(), amount);
+      self.get_account(to.clone()).expect("Account not found").deposit_tokens(token, amount);
+    }
+    self.add_transfer(None, from.clone(), to.clone(), amount)
+  }
+}
+
 #[near_bindgen]
 impl Contract {
-  pub fn add_transfer(&mut self, id: String, sender: AccountId, receiver: AccountId, amount: u128) {
+  /// Pays near (asset "near") or a token from the caller's balance to every
+  /// recipient, or to none of them. The send fee is charged once in near.
+  pub fn batch_transfer(&mut self, asset: String, recipients: Vec<BatchRecipient>) -> String {
+    self.assert_not_emergency();
+    let sender = env::predecessor_account_id();
+    if recipients.is_empty() {
+      return "No recipients".to_string();
+    }
+    if recipients.len() > MAX_BATCH_RECIPIENTS {
+      return format!("A batch can't have more than {} recipients", MAX_BATCH_RECIPIENTS);
+    }
+    if self.pub_get_account(sender.clone()).is_none() {
+      return "You must be a registered user to send transfers".to_string();
+    }
+    if asset != NEAR_ASSET && asset.parse().ok().and_then(|token| self.get_token(token)).is_none() {
+      return format!("Token {} is not supported", asset);
+    }
+    for recipient in recipients.iter() {
+      if recipient.account_id == sender {
+        return "You can't send to yourself".to_string();
+      }
+      if recipient.amount.0 == 0 {
+        return "Amount must be greater than zero".to_string();
+      }
+      if self.pub_get_account(recipient.account_id.clone()).is_none() {
+        return format!("Recipient {} is not registered", recipient.account_id);
+      }
+      if let Some(err) = self.check_denylisted(&[&sender, &recipient.account_id]) {
+        return err;
+      }
+    }
+
+    let total: Balance = recipients.iter().map(|recipient| recipient.amount.0).sum();
+    let fee = self.send_fee();
+    let near_needed = if asset == NEAR_ASSET { total + fee } else { fee };
+    if self.free_balance(&sender, NEAR_ASSET) < near_needed {
+      return "You do not have enough balance for the batch and its fee".to_string();
+    }
+    if asset != NEAR_ASSET && self.free_balance(&sender, &asset) < total {
+      return "You do not have enough token balance for the batch".to_string();
+    }
+
+    for recipient in recipients.iter() {
+      self.move_balance(&sender, &recipient.account_id, &asset, recipient.amount.0);
+    }
+    if fee > 0 {
+      let fee_usd = self.oracle_price.as_ref().map_or(0.0, |price| price.price * fee as f64 / ONE_NEAR as f64);
+      self.get_account(sender.clone()).unwrap().lock(fee);
+      self.record_revenue(NEAR_ASSET.to_string(), "transfer".to_string(), sender, fee, fee_usd);
+    }
+    format!("Sent to {} recipients", recipients.len())
+  }
+
+  pub fn add_transfer(&mut self, id: Option<String>, sender: AccountId, receiver: AccountId, amount: u128) -> String {
+    let id = self
+      .resolve_id(TRANSFER_ID_PREFIX, id, |c, id| c.get_transfer(id.clone()).is_some())
+      .unwrap_or_else(|err| env::panic_str(&err));
     let transfer = Transfer::new(id.clone(), sender, receiver, amount);
     self.transfers.push(&transfer);
+    id
   }
 
   pub fn get_transfers_len(&self) -> u64 {
@@ -0,0 +1,719 @@
+use near_sdk::json_types::U128;
+use near_sdk::{
+  borsh::{self, BorshDeserialize, BorshSerialize},
+  serde::{Deserialize, Serialize},
+  AccountId, Balance, Promise, Timestamp,
+};
+
+use crate::account::Account;
+use crate::constants::GAS_FOR_MIGRATE;
+use crate::offer::OfferRequirements;
+use crate::offerbook::NEAR_ASSET;
+use crate::*;
+
+// Stored records are wrapped in a version enum so a layout change adds a
+// variant instead of breaking Borsh deserialization of the existing state.
+// Reads convert older variants to the current struct, writes store the current
+// variant, so chats and offers are upgraded lazily the next time they change.
+// Accounts need their token maps written, migrate_state converts them in pages.
+
+// Contract state of the baseline deployment, before records were versioned.
+// Its collections keep the byte prefixes they were created with.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV0 {
+  pub owner_id: AccountId,
+  pub proposed_owner_id: AccountId,
+  pub guardians: UnorderedSet<AccountId>,
+  pub accounts: HashMap<AccountId, AccountV0>,
+  pub trades: Vector<Trade>,
+  pub transfers: Vector<Transfer>,
+  pub tokenswaps: LookupMap<String, TokenSwap>,
+  pub offers: UnorderedMap<String, OfferV0>,
+  pub tokenoffers: UnorderedMap<String, TokenOfferV0>,
+  pub chats: UnorderedMap<String, ChatV0>,
+  pub tokenchats: UnorderedMap<String, TokenChatV0>,
+  pub transfer_cost: u32,
+  pub send_cost: f32,
+  pub tokens: UnorderedMap<AccountId, TokenMetadata>,
+  pub whitelistedtokens: UnorderedMap<AccountId, TokenMetadata>,
+  pub payment_methods: UnorderedMap<String, PaymentMethod>,
+  pub revenue: u128,
+  pub revenue_usd: f64,
+  pub revenues: UnorderedSet<Revenue>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct AccountV0 {
+  pub id: AccountId,
+  pub balance: Balance,
+  pub locked: Balance,
+  pub likes: i32,
+  pub dislikes: i32,
+  pub tokens: HashMap<AccountId, Balance>,
+  pub locked_tokens: HashMap<AccountId, Balance>,
+  pub blocked_by: i32,
+  pub created_on: Timestamp,
+}
+
+// Offers had a single payment method
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OfferV0 {
+  pub id: String,
+  pub offer_type: String,
+  pub offerer: AccountId,
+  pub min_amount: u128,
+  pub max_amount: u128,
+  pub offer_rate: f32,
+  pub active: bool,
+  pub payment: String,
+  pub currency: String,
+  pub instructions: String,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenOfferV0 {
+  pub id: String,
+  pub offer_type: String,
+  pub offerer: AccountId,
+  pub min_amount: u128,
+  pub max_amount: u128,
+  pub offer_rate: f32,
+  pub active: bool,
+  pub token: AccountId,
+  pub payment: String,
+  pub currency: String,
+  pub instructions: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+// Chats read their rate and payment method from the offer
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct ChatV0 {
+  pub id: String,
+  pub offer_id: String,
+  pub owner: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub trade_cost: Balance,
+  pub trade_cost_usd: f64,
+  pub started_at: Timestamp,
+  pub ended_at: Option<Timestamp>,
+  pub active: bool,
+  pub payer: AccountId,
+  pub receiver: AccountId,
+  pub paid: bool,
+  pub received: bool,
+  pub canceled: bool,
+  pub released: bool,
+  pub payer_has_rated: bool,
+  pub receiver_has_rated: bool,
+  pub payment_msg: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct TokenChatV0 {
+  pub id: String,
+  pub offer_id: String,
+  pub token_id: AccountId,
+  pub owner: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub trade_cost: Balance,
+  pub trade_cost_usd: f64,
+  pub started_at: Timestamp,
+  pub ended_at: Option<Timestamp>,
+  pub active: bool,
+  pub payer: AccountId,
+  pub receiver: AccountId,
+  pub paid: bool,
+  pub received: bool,
+  pub canceled: bool,
+  pub released: bool,
+  pub payer_has_rated: bool,
+  pub receiver_has_rated: bool,
+  pub payment_msg: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+// Records of the baseline deployment migrate_state has not moved yet, under
+// their old prefixes
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct PendingMigration {
+  pub accounts: HashMap<AccountId, AccountV0>,
+  pub offers: UnorderedMap<String, OfferV0>,
+  pub tokenoffers: UnorderedMap<String, TokenOfferV0>,
+  pub chats: UnorderedMap<String, ChatV0>,
+  pub tokenchats: UnorderedMap<String, TokenChatV0>,
+}
+
+#[derive(Serialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VersionedAccount {
+  Current(Account),
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VersionedChat {
+  V1(ChatV1),
+  V2(ChatV2),
+  Current(Chat),
+}
+
+// Chat before partial releases added released_amount and fee_collected
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChatV1 {
+  pub id: String,
+  pub offer_id: String,
+  pub owner: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub rate: f32,
+  pub trade_cost: Balance,
+  pub trade_cost_usd: f64,
+  pub started_at: Timestamp,
+  pub ended_at: Option<Timestamp>,
+  pub active: bool,
+  pub payer: AccountId,
+  pub receiver: AccountId,
+  pub paid: bool,
+  pub received: bool,
+  pub canceled: bool,
+  pub released: bool,
+  pub payer_has_rated: bool,
+  pub receiver_has_rated: bool,
+  pub payment_method: String,
+  pub payment_msg: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+// Chat before rate negotiation added fiat_total
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChatV2 {
+  pub id: String,
+  pub offer_id: String,
+  pub owner: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub rate: f32,
+  pub trade_cost: Balance,
+  pub trade_cost_usd: f64,
+  pub started_at: Timestamp,
+  pub ended_at: Option<Timestamp>,
+  pub active: bool,
+  pub payer: AccountId,
+  pub receiver: AccountId,
+  pub paid: bool,
+  pub received: bool,
+  pub canceled: bool,
+  pub released: bool,
+  pub released_amount: Balance,
+  pub fee_collected: Balance,
+  pub payer_has_rated: bool,
+  pub receiver_has_rated: bool,
+  pub payment_method: String,
+  pub payment_msg: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VersionedTokenChat {
+  V1(TokenChatV1),
+  Current(TokenChat),
+}
+
+// TokenChat before partial releases added released_amount and fee_collected
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenChatV1 {
+  pub id: String,
+  pub offer_id: String,
+  pub token_id: AccountId,
+  pub owner: AccountId,
+  pub offerer: AccountId,
+  pub amount: Balance,
+  pub trade_cost: Balance,
+  pub trade_cost_usd: f64,
+  pub started_at: Timestamp,
+  pub ended_at: Option<Timestamp>,
+  pub active: bool,
+  pub payer: AccountId,
+  pub receiver: AccountId,
+  pub paid: bool,
+  pub received: bool,
+  pub canceled: bool,
+  pub released: bool,
+  pub payer_has_rated: bool,
+  pub receiver_has_rated: bool,
+  pub payment_method: String,
+  pub payment_msg: String,
+  pub created_on: Timestamp,
+  pub updated_on: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VersionedOffer {
+  V0(OfferV0),
+  Current(Offer),
+}
+
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum VersionedTokenOffer {
+  V0(TokenOfferV0),
+  Current(TokenOffer),
+}
+
+impl From<VersionedAccount> for Account {
+  fn from(account: VersionedAccount) -> Self {
+    match account {
+      VersionedAccount::Current(acc) => acc,
+    }
+  }
+}
+
+impl From<Account> for VersionedAccount {
+  fn from(account: Account) -> Self {
+    VersionedAccount::Current(account)
+  }
+}
+
+fn offer_payment(method: String, instructions: String, min_amount: u128, max_amount: u128) -> Vec<OfferPayment> {
+  vec![OfferPayment {
+    method,
+    instructions,
+    min_amount: U128(min_amount),
+    max_amount: U128(max_amount),
+  }]
+}
+
+impl From<AccountV0> for Account {
+  fn from(acc: AccountV0) -> Self {
+    let mut account = Account::new(acc.id);
+    account.balance = acc.balance;
+    account.locked = acc.locked;
+    account.likes = acc.likes;
+    account.dislikes = acc.dislikes;
+    account.blocked_by = acc.blocked_by;
+    account.created_on = acc.created_on;
+    for (token, amount) in acc.tokens.iter() {
+      account.tokens.insert(token, amount);
+    }
+    for (token, amount) in acc.locked_tokens.iter() {
+      account.locked_tokens.insert(token, amount);
+    }
+    account
+  }
+}
+
+impl From<OfferV0> for Offer {
+  fn from(offer: OfferV0) -> Self {
+    Offer {
+      id: offer.id,
+      offer_type: offer.offer_type,
+      offerer: offer.offerer,
+      min_amount: offer.min_amount,
+      max_amount: offer.max_amount,
+      offer_rate: offer.offer_rate,
+      margin: None,
+      active: offer.active,
+      payments: offer_payment(offer.payment, offer.instructions.clone(), offer.min_amount, offer.max_amount),
+      currency: offer.currency,
+      instructions: offer.instructions,
+      // Not recorded before
+      created_on: 0,
+      requirements: OfferRequirements::default(),
+    }
+  }
+}
+
+impl From<TokenOfferV0> for TokenOffer {
+  fn from(offer: TokenOfferV0) -> Self {
+    TokenOffer {
+      id: offer.id,
+      offer_type: offer.offer_type,
+      offerer: offer.offerer,
+      min_amount: offer.min_amount,
+      max_amount: offer.max_amount,
+      offer_rate: offer.offer_rate,
+      active: offer.active,
+      token: offer.token,
+      payments: offer_payment(offer.payment, offer.instructions.clone(), offer.min_amount, offer.max_amount),
+      currency: offer.currency,
+      instructions: offer.instructions,
+      created_on: offer.created_on,
+      updated_on: offer.updated_on,
+      requirements: OfferRequirements::default(),
+    }
+  }
+}
+
+impl ChatV0 {
+  fn into_v1(self, rate: f32, payment_method: String) -> ChatV1 {
+    ChatV1 {
+      id: self.id,
+      offer_id: self.offer_id,
+      owner: self.owner,
+      offerer: self.offerer,
+      amount: self.amount,
+      rate,
+      trade_cost: self.trade_cost,
+      trade_cost_usd: self.trade_cost_usd,
+      started_at: self.started_at,
+      ended_at: self.ended_at,
+      active: self.active,
+      payer: self.payer,
+      receiver: self.receiver,
+      paid: self.paid,
+      received: self.received,
+      canceled: self.canceled,
+      released: self.released,
+      payer_has_rated: self.payer_has_rated,
+      receiver_has_rated: self.receiver_has_rated,
+      payment_method,
+      payment_msg: self.payment_msg,
+      created_on: self.created_on,
+      updated_on: self.updated_on,
+    }
+  }
+}
+
+impl TokenChatV0 {
+  fn into_v1(self, payment_method: String) -> TokenChatV1 {
+    TokenChatV1 {
+      id: self.id,
+      offer_id: self.offer_id,
+      token_id: self.token_id,
+      owner: self.owner,
+      offerer: self.offerer,
+      amount: self.amount,
+      trade_cost: self.trade_cost,
+      trade_cost_usd: self.trade_cost_usd,
+      started_at: self.started_at,
+      ended_at: self.ended_at,
+      active: self.active,
+      payer: self.payer,
+      receiver: self.receiver,
+      paid: self.paid,
+      received: self.received,
+      canceled: self.canceled,
+      released: self.released,
+      payer_has_rated: self.payer_has_rated,
+      receiver_has_rated: self.receiver_has_rated,
+      payment_method,
+      payment_msg: self.payment_msg,
+      created_on: self.created_on,
+      updated_on: self.updated_on,
+    }
+  }
+}
+
+impl From<ChatV1> for ChatV2 {
+  fn from(chat: ChatV1) -> Self {
+    ChatV2 {
+      id: chat.id,
+      offer_id: chat.offer_id,
+      owner: chat.owner,
+      offerer: chat.offerer,
+      amount: chat.amount,
+      rate: chat.rate,
+      trade_cost: chat.trade_cost,
+      trade_cost_usd: chat.trade_cost_usd,
+      started_at: chat.started_at,
+      ended_at: chat.ended_at,
+      active: chat.active,
+      payer: chat.payer,
+      receiver: chat.receiver,
+      paid: chat.paid,
+      received: chat.received,
+      canceled: chat.canceled,
+      released: chat.released,
+      // Released chats were released in full
+      released_amount: if chat.released { chat.amount } else { 0 },
+      fee_collected: if chat.released { chat.trade_cost } else { 0 },
+      payer_has_rated: chat.payer_has_rated,
+      receiver_has_rated: chat.receiver_has_rated,
+      payment_method: chat.payment_method,
+      payment_msg: chat.payment_msg,
+      created_on: chat.created_on,
+      updated_on: chat.updated_on,
+    }
+  }
+}
+
+impl From<ChatV2> for Chat {
+  fn from(chat: ChatV2) -> Self {
+    Chat {
+      id: chat.id,
+      offer_id: chat.offer_id,
+      owner: chat.owner,
+      offerer: chat.offerer,
+      amount: chat.amount,
+      rate: chat.rate,
+      fiat_total: crate::chat::fiat_total(chat.amount, chat.rate),
+      trade_cost: chat.trade_cost,
+      trade_cost_usd: chat.trade_cost_usd,
+      started_at: chat.started_at,
+      ended_at: chat.ended_at,
+      active: chat.active,
+      payer: chat.payer,
+      receiver: chat.receiver,
+      paid: chat.paid,
+      received: chat.received,
+      canceled: chat.canceled,
+      released: chat.released,
+      released_amount: chat.released_amount,
+      fee_collected: chat.fee_collected,
+      payer_has_rated: chat.payer_has_rated,
+      receiver_has_rated: chat.receiver_has_rated,
+      payment_method: chat.payment_method,
+      payment_msg: chat.payment_msg,
+      created_on: chat.created_on,
+      updated_on: chat.updated_on,
+    }
+  }
+}
+
+impl From<VersionedChat> for Chat {
+  fn from(chat: VersionedChat) -> Self {
+    match chat {
+      VersionedChat::V1(chat) => Chat::from(ChatV2::from(chat)),
+      VersionedChat::V2(chat) => Chat::from(chat),
+      VersionedChat::Current(chat) => chat,
+    }
+  }
+}
+
+impl From<Chat> for VersionedChat {
+  fn from(chat: Chat) -> Self {
+    VersionedChat::Current(chat)
+  }
+}
+
+impl From<TokenChatV1> for TokenChat {
+  fn from(chat: TokenChatV1) -> Self {
+    TokenChat {
+      id: chat.id,
+      offer_id: chat.offer_id,
+      token_id: chat.token_id,
+      owner: chat.owner,
+      offerer: chat.offerer,
+      amount: chat.amount,
+      trade_cost: chat.trade_cost,
+      trade_cost_usd: chat.trade_cost_usd,
+      started_at: chat.started_at,
+      ended_at: chat.ended_at,
+      active: chat.active,
+      payer: chat.payer,
+      receiver: chat.receiver,
+      paid: chat.paid,
+      received: chat.received,
+      canceled: chat.canceled,
+      released: chat.released,
+      // Released chats were released in full
+      released_amount: if chat.released { chat.amount } else { 0 },
+      fee_collected: if chat.released { chat.trade_cost } else { 0 },
+      payer_has_rated: chat.payer_has_rated,
+      receiver_has_rated: chat.receiver_has_rated,
+      payment_method: chat.payment_method,
+      payment_msg: chat.payment_msg,
+      created_on: chat.created_on,
+      updated_on: chat.updated_on,
+    }
+  }
+}
+
+impl From<VersionedTokenChat> for TokenChat {
+  fn from(chat: VersionedTokenChat) -> Self {
+    match chat {
+      VersionedTokenChat::V1(chat) => TokenChat::from(chat),
+      VersionedTokenChat::Current(chat) => chat,
+    }
+  }
+}
+
+impl From<TokenChat> for VersionedTokenChat {
+  fn from(chat: TokenChat) -> Self {
+    VersionedTokenChat::Current(chat)
+  }
+}
+
+impl From<VersionedOffer> for Offer {
+  fn from(offer: VersionedOffer) -> Self {
+    match offer {
+      VersionedOffer::V0(offer) => Offer::from(offer),
+      VersionedOffer::Current(offer) => offer,
+    }
+  }
+}
+
+impl From<Offer> for VersionedOffer {
+  fn from(offer: Offer) -> Self {
+    VersionedOffer::Current(offer)
+  }
+}
+
+impl From<VersionedTokenOffer> for TokenOffer {
+  fn from(offer: VersionedTokenOffer) -> Self {
+    match offer {
+      VersionedTokenOffer::V0(offer) => TokenOffer::from(offer),
+      VersionedTokenOffer::Current(offer) => offer,
+    }
+  }
+}
+
+impl From<TokenOffer> for VersionedTokenOffer {
+  fn from(offer: TokenOffer) -> Self {
+    VersionedTokenOffer::Current(offer)
+  }
+}
+
+// Last key of a legacy map, removing it doesn't move any other entry
+fn last_key<V: BorshSerialize + BorshDeserialize>(map: &UnorderedMap<String, V>) -> Option<String> {
+  let keys = map.keys_as_vector();
+  keys.get(keys.len().checked_sub(1)?)
+}
+
+impl PendingMigration {
+  fn is_empty(&self) -> bool {
+    self.accounts.is_empty()
+      && self.chats.is_empty()
+      && self.tokenchats.is_empty()
+      && self.offers.is_empty()
+      && self.tokenoffers.is_empty()
+  }
+
+  // Moves one record to the current collections, returns false once nothing
+  // is left. Chats go first as they read their rate from the legacy offer.
+  fn migrate_next(&mut self, contract: &mut Contract) -> bool {
+    if let Some(id) = self.accounts.keys().next().cloned() {
+      let acc = self.accounts.remove(&id).unwrap();
+      contract.accounts.insert(&id, &VersionedAccount::from(Account::from(acc)));
+    } else if let Some(id) = last_key(&self.chats) {
+      let chat = self.chats.remove(&id).unwrap();
+      let (rate, payment_method) = self
+        .offers
+        .get(&chat.offer_id)
+        .map_or((0.0, String::new()), |offer| (offer.offer_rate, offer.payment));
+      contract.index_offer_chat(&chat.offer_id, &id);
+      contract.chats.insert(&id, &VersionedChat::V1(chat.into_v1(rate, payment_method)));
+    } else if let Some(id) = last_key(&self.tokenchats) {
+      let chat = self.tokenchats.remove(&id).unwrap();
+      let payment_method = self.tokenoffers.get(&chat.offer_id).map_or(String::new(), |offer| offer.payment);
+      contract.index_token_offer_chat(&chat.offer_id, &id);
+      contract.tokenchats.insert(&id, &VersionedTokenChat::V1(chat.into_v1(payment_method)));
+    } else if let Some(id) = last_key(&self.offers) {
+      let offer = self.offers.remove(&id).unwrap();
+      contract.index_offer(NEAR_ASSET, &offer.currency, &id);
+      contract.offers.insert(&id, &VersionedOffer::V0(offer));
+    } else if let Some(id) = last_key(&self.tokenoffers) {
+      let offer = self.tokenoffers.remove(&id).unwrap();
+      contract.index_offer(offer.token.as_str(), &offer.currency, &id);
+      contract.tokenoffers.insert(&id, &VersionedTokenOffer::V0(offer));
+    } else {
+      return false;
+    }
+    true
+  }
+}
+
+#[near_bindgen]
+impl Contract {
+  /// Deploys the wasm passed as the raw call input and calls migrate on it.
+  /// Only can be called by owner.
+  pub fn upgrade(&self) -> Promise {
+    self.assert_owner();
+    let code = env::input().expect("Expected the new contract code as input");
+    Promise::new(env::current_account_id())
+      .deploy_contract(code)
+      .function_call("migrate".to_string(), vec![], 0, GAS_FOR_MIGRATE)
+  }
+
+  /// Reads the state left by the baseline deployment and maps it to the
+  /// current layout. Fields added since start from their defaults, the
+  /// accounts, offers and chats wait in `migration` for migrate_state, which
+  /// moves them in pages so no single call has to rewrite the whole state.
+  #[private]
+  #[init(ignore_state)]
+  pub fn migrate() -> Self {
+    let ContractV0 {
+      owner_id,
+      proposed_owner_id,
+      guardians,
+      accounts,
+      trades,
+      transfers,
+      tokenswaps,
+      offers,
+      tokenoffers,
+      chats,
+      tokenchats,
+      transfer_cost,
+      send_cost,
+      tokens,
+      whitelistedtokens,
+      payment_methods,
+      revenue,
+      revenue_usd,
+      revenues,
+    } = env::state_read().expect("Contract state not found");
+    // Collections whose records kept their layout stay under their old prefixes
+    Contract {
+      owner_id,
+      proposed_owner_id,
+      guardians,
+      trades,
+      transfers,
+      tokenswaps,
+      transfer_cost,
+      send_cost,
+      tokens,
+      whitelistedtokens,
+      payment_methods,
+      revenue,
+      revenue_usd,
+      revenues,
+      migration: Some(PendingMigration {
+        accounts,
+        offers,
+        tokenoffers,
+        chats,
+        tokenchats,
+      }),
+      ..Contract::default()
+    }
+  }
+
+  /// Moves up to `limit` records left by migrate to the current layout and
+  /// returns whether the migration is done. Everything but views stays
+  /// stopped until it is. Only can be called by owner.
+  pub fn migrate_state(&mut self, limit: u32) -> bool {
+    self.assert_owner();
+    let mut pending = match self.migration.take() {
+      Some(pending) => pending,
+      None => return true,
+    };
+    for _ in 0..limit {
+      if !pending.migrate_next(self) {
+        break;
+      }
+    }
+    let done = pending.is_empty();
+    if !done {
+      self.migration = Some(pending);
+    }
+    done
+  }
+}
@@ -0,0 +1,9 @@
+#!/usr/bin/env bash
+
+# Upgrades an already initialized contract in place, keeping its state
+WASM_PATH="$(find ./target/wasm32-unknown-unknown/release/ -maxdepth 1 -name "*.wasm")"
+
+near call "$1" upgrade \
+  --base64 "$(base64 -w0 $WASM_PATH)" \
+  --accountId "$1" \
+  --gas 300000000000000